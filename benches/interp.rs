@@ -0,0 +1,24 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use cbt_fuck::bf2c::bf2c::parse;
+use cbt_fuck::bf2c::interp::{run_prog, run_symbols, Tape};
+use cbt_fuck::bf2c::localop::optimize;
+
+/// Nested counting loops full of copy and clear loops, the shapes the
+/// local optimizations target.
+const PROGRAM: &str = "++++++++[>++++++++[>++++++++[>+>++<<-]>[-<+>]>[-]<<<-]<-]>>>[<+>-]<[>+>+<<-]";
+
+fn interp_benchmark(c: &mut Criterion) {
+    let tokens = parse(PROGRAM, true).unwrap();
+    let prog = optimize(&tokens);
+
+    c.bench_function("run_symbols", |b| {
+        b.iter(|| run_symbols(&tokens, &mut Tape::new(), &mut &b""[..], &mut Vec::new()).unwrap())
+    });
+    c.bench_function("run_prog", |b| {
+        b.iter(|| run_prog(&prog, &mut Tape::new(), &mut &b""[..], &mut Vec::new()).unwrap())
+    });
+}
+
+criterion_group!(benches, interp_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,148 @@
+//! Brainfuck programs embedded in Rust, parsed and optimized at compile time.
+//!
+//! `bf!` and `bf_file!` expand to a function
+//! `fn(&mut dyn Read, &mut dyn Write) -> io::Result<()>` running the program
+//! on a tape of `TAPE_SIZE` cells. The generated code is plain Rust, with no
+//! interpreter and no dependency on the transpiler at run time.
+//!
+//! ```
+//! use bf2c_macros::bf;
+//!
+//! let program = bf!("++++++++[>++++++++<-]>+.+.+.");
+//! let mut output = Vec::new();
+//! program(&mut std::io::empty(), &mut output).unwrap();
+//! assert_eq!(output, b"ABC");
+//! ```
+//!
+//! Unmatched brackets are compile errors. Cells wrap at 8 bits, end of input
+//! reads as 255 and moving off the tape returns an error, as in the
+//! interpreter.
+
+use std::path::PathBuf;
+
+use cbt_fuck::bf2c::bf2c::parse;
+use cbt_fuck::bf2c::localop::{inverse_mod_256, optimize, Prog, Stmt};
+use cbt_fuck::bf2c::transpiler::TAPE_SIZE;
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, LitStr};
+
+/// The program in the string literal.
+#[proc_macro]
+pub fn bf(input: TokenStream) -> TokenStream {
+    let literal = parse_macro_input!(input as LitStr);
+    expand(&literal.value(), &literal, quote! {}).into()
+}
+
+/// The program in a file, relative to the directory of the crate's
+/// `Cargo.toml`. The crate is rebuilt when the file changes.
+#[proc_macro]
+pub fn bf_file(input: TokenStream) -> TokenStream {
+    let literal = parse_macro_input!(input as LitStr);
+    let dir = std::env::var_os("CARGO_MANIFEST_DIR").map_or_else(PathBuf::new, PathBuf::from);
+    let path = dir.join(literal.value());
+    let source = match std::fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(e) => return error(&literal, &format!("cannot read '{}': {}", path.display(), e)).into(),
+    };
+    let path = path.to_string_lossy().into_owned();
+    // makes cargo track the file
+    expand(&source, &literal, quote! { const _: &str = include_str!(#path); }).into()
+}
+
+fn expand(source: &str, literal: &LitStr, prelude: TokenStream2) -> TokenStream2 {
+    let tokens = match parse(source, true) {
+        Ok(tokens) => tokens,
+        Err(e) => return error(literal, &e.to_string()),
+    };
+    let body = block(&optimize(&tokens));
+    quote! {{
+        #prelude
+        fn program(input: &mut dyn ::std::io::Read, output: &mut dyn ::std::io::Write) -> ::std::io::Result<()> {
+            const TAPE_SIZE: usize = #TAPE_SIZE;
+            fn shift(ptr: usize, distance: isize) -> ::std::io::Result<usize> {
+                match ptr.checked_add_signed(distance) {
+                    Some(ptr) if ptr < TAPE_SIZE => Ok(ptr),
+                    _ => Err(::std::io::Error::other(format!("data pointer moved out of the tape (cell {})", ptr as isize + distance))),
+                }
+            }
+            fn read(input: &mut dyn ::std::io::Read) -> ::std::io::Result<u8> {
+                let mut buf = [0u8; 1];
+                Ok(if input.read(&mut buf)? == 0 { 255 } else { buf[0] })
+            }
+            let mut tape = ::std::vec![0u8; TAPE_SIZE];
+            let mut ptr = 0usize;
+            #body
+            output.flush()
+        }
+        program
+    }}
+}
+
+fn block(prog: &Prog) -> TokenStream2 {
+    prog.iter().map(stmt).collect()
+}
+
+fn stmt(stmt: &Stmt) -> TokenStream2 {
+    match stmt {
+        Stmt::Add(delta) => {
+            let delta = *delta as u8;
+            quote! { tape[ptr] = tape[ptr].wrapping_add(#delta); }
+        }
+        Stmt::Move(distance) => {
+            let distance = *distance as isize;
+            quote! { ptr = shift(ptr, #distance)?; }
+        }
+        Stmt::Output(count) => quote! {
+            for _ in 0..#count {
+                output.write_all(&[tape[ptr]])?;
+            }
+        },
+        Stmt::Input(count) => quote! {
+            for _ in 0..#count {
+                tape[ptr] = read(input)?;
+            }
+        },
+        Stmt::Loop(body) => {
+            let body = block(body);
+            quote! {
+                while tape[ptr] != 0 {
+                    #body
+                }
+            }
+        }
+        Stmt::ZeroLoop => quote! { tape[ptr] = 0; },
+        Stmt::ScanLoop(direction) => {
+            let direction = *direction as isize;
+            quote! {
+                while tape[ptr] != 0 {
+                    ptr = shift(ptr, #direction)?;
+                }
+            }
+        }
+        Stmt::MultiplicationLoop(decrement, effects) => {
+            let inverse = inverse_mod_256(*decrement as u8);
+            let effects = effects.iter().map(|&(offset, factor)| {
+                let (offset, factor) = (offset as isize, factor as u8);
+                quote! {
+                    let cell = shift(ptr, #offset)?;
+                    tape[cell] = tape[cell].wrapping_add(#factor.wrapping_mul(n));
+                }
+            });
+            quote! {
+                if tape[ptr] != 0 {
+                    let n = tape[ptr].wrapping_mul(#inverse);
+                    #(#effects)*
+                    tape[ptr] = 0;
+                }
+            }
+        }
+        // `parse` keeps `#` as a comment
+        Stmt::Debug => quote! {},
+    }
+}
+
+fn error(literal: &LitStr, message: &str) -> TokenStream2 {
+    syn::Error::new(literal.span(), message).to_compile_error()
+}
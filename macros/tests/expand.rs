@@ -0,0 +1,18 @@
+use bf2c_macros::{bf, bf_file};
+use std::io;
+
+#[test]
+fn runs_embedded_programs() {
+    let mut output = Vec::new();
+    bf_file!("tests/hello.bf")(&mut io::empty(), &mut output).unwrap();
+    assert_eq!(output, b"Hello World!\n");
+
+    // echoes input up to a NUL, keeping every byte on the tape
+    let echo = bf!(",[.>,]");
+    let mut output = Vec::new();
+    echo(&mut &b"echo\0"[..], &mut output).unwrap();
+    assert_eq!(output, b"echo");
+
+    let error = bf!("+[<+]")(&mut io::empty(), &mut Vec::new()).unwrap_err();
+    assert_eq!(error.to_string(), "data pointer moved out of the tape (cell -1)");
+}
@@ -0,0 +1,212 @@
+//! Static checks reporting likely bugs in a program without running it.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use super::bf2c::{parse_with_spans, BfSymbol, ParseOptions, Span};
+pub use super::diagnostic::{Code, Diagnostic, Diagnostics, Severity};
+use super::localop::{optimize_with_ranges, Prog, Stmt};
+
+/// The line of `source` containing the byte `offset`, with a caret under
+/// the character at `offset`:
+///
+/// ```text
+///   |
+/// 2 | [[-]
+///   | ^
+/// ```
+pub fn snippet(source: &str, offset: usize) -> String {
+    let (line, column) = line_column(source, offset);
+    let text = source.lines().nth(line - 1).unwrap_or("");
+    let gutter = " ".repeat(line.to_string().len());
+    format!("{} |\n{} | {}\n{} | {}^", gutter, line, text, gutter, " ".repeat(column - 1))
+}
+
+/// 1-based line and column, in characters, of the byte `offset` in `source`.
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Checks `source` for unmatched brackets, loops that may never terminate and
+/// pointer moves left of the first cell. Diagnostics are sorted by offset.
+pub fn check(source: &str, options: &ParseOptions) -> Diagnostics {
+    let (tokens, spans) =
+        parse_with_spans(source, false, options).expect("parsing without verification cannot fail");
+    let mut diagnostics = unmatched_brackets(source, &tokens, &spans);
+    if diagnostics.is_empty() {
+        let (prog, ranges) = optimize_with_ranges(&tokens);
+        let mut walker = Walker { source, ranges: &ranges, spans: &spans, next: 0, diagnostics: &mut diagnostics };
+        walker.block(&prog, Some(0));
+    }
+    diagnostics.sort();
+    diagnostics
+}
+
+/// `bytes` of `source` as a span.
+fn span(source: &str, bytes: Range<usize>) -> Span {
+    let (line, column) = line_column(source, bytes.start);
+    Span { start: bytes.start, end: bytes.end, line, column }
+}
+
+fn unmatched_brackets(source: &str, tokens: &[BfSymbol], spans: &[Range<usize>]) -> Diagnostics {
+    let mut diagnostics = Diagnostics::new();
+    let mut open = Vec::new();
+    for (token, bytes) in tokens.iter().zip(spans) {
+        match token {
+            BfSymbol::OpenBracket => open.push(bytes.clone()),
+            BfSymbol::CloseBracket if open.pop().is_none() => {
+                let diagnostic = Diagnostic::new(Code::UnmatchedClose, Severity::Error, "']' has no matching '['");
+                diagnostics.push(diagnostic.with_span(span(source, bytes.clone())));
+            }
+            _ => {}
+        }
+    }
+    diagnostics.extend(open.into_iter().map(|bytes| {
+        Diagnostic::new(Code::UnclosedOpen, Severity::Error, "'[' is never closed").with_span(span(source, bytes))
+    }));
+    diagnostics
+}
+
+/// Walks the IR in the pre-order of `optimize_with_ranges`, tracking the
+/// pointer position while it is statically known.
+struct Walker<'a> {
+    source: &'a str,
+    ranges: &'a [Range<usize>],
+    /// Byte range of every token.
+    spans: &'a [Range<usize>],
+    next: usize,
+    diagnostics: &'a mut Diagnostics,
+}
+
+impl Walker<'_> {
+    /// Checks `prog`, starting at cell `position` if known. Returns the
+    /// position after the block, if still known.
+    fn block(&mut self, prog: &Prog, mut position: Option<i64>) -> Option<i64> {
+        for stmt in prog {
+            let tokens = self.ranges[self.next].clone();
+            let span = span(self.source, self.spans[tokens.start].start..self.spans[tokens.end - 1].end);
+            self.next += 1;
+            position = match stmt {
+                Stmt::Move(distance) => position.map(|p| p + *distance as i64),
+                Stmt::ScanLoop(_) => None,
+                Stmt::MultiplicationLoop(_, effects) => {
+                    if let Some(p) = position {
+                        if effects.iter().any(|&(cell, _)| p + (cell as i64) < 0) {
+                            self.warn_underflow(span);
+                        }
+                    }
+                    position
+                }
+                Stmt::Loop(body) => {
+                    self.check_termination(body, span);
+                    // the body runs from the loop's position only if it is balanced
+                    let after = self.block(body, position);
+                    if after == position { position } else { None }
+                }
+                _ => position,
+            };
+            if position.is_some_and(|p| p < 0) {
+                self.warn_underflow(span);
+                // report each underflow once
+                position = None;
+            }
+        }
+        position
+    }
+
+    /// Warns about loops whose body cannot bring every starting value of the
+    /// cell down to zero. Odd `[+]`/`[-]` loops are already `ZeroLoop`s.
+    fn check_termination(&mut self, body: &Prog, span: Span) {
+        let diagnostic = match body.as_slice() {
+            [] => Diagnostic::new(Code::EmptyLoop, Severity::Warning, "empty loop never terminates once entered"),
+            [Stmt::Add(delta)] => {
+                let message = format!(
+                    "loop adding {} only terminates if the cell starts as a multiple of {}",
+                    delta,
+                    1 << delta.trailing_zeros().min(8)
+                );
+                Diagnostic::new(Code::LoopMayNotTerminate, Severity::Warning, &message)
+                    .with_note("a loop adding an odd amount, like `[-]`, clears any cell")
+            }
+            _ => return,
+        };
+        self.diagnostics.push(diagnostic.with_span(span));
+    }
+
+    fn warn_underflow(&mut self, span: Span) {
+        let diagnostic = Diagnostic::new(Code::PointerUnderflow, Severity::Warning, "pointer moves left of the first cell");
+        self.diagnostics.push(diagnostic.with_span(span));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::super::bf2c::ParseOptions;
+    use super::{check, line_column, snippet, Severity};
+
+    fn messages(source: &str) -> Vec<(usize, Severity, String)> {
+        check(source, &ParseOptions::default())
+            .into_iter()
+            .map(|diagnostic| (diagnostic.offset().unwrap(), diagnostic.severity, diagnostic.message))
+            .collect()
+    }
+
+    #[test]
+    fn line_and_column() {
+        assert_eq!(line_column("+\n é[", 0), (1, 1));
+        assert_eq!(line_column("+\n é[", 2), (2, 1));
+        assert_eq!(line_column("+\n é[", 5), (2, 3));
+    }
+
+    #[test]
+    fn unmatched_brackets() {
+        let found = messages("+]\n[[-]");
+        assert_eq!(
+            found,
+            vec![
+                (1, Severity::Error, "']' has no matching '['".to_string()),
+                (3, Severity::Error, "'[' is never closed".to_string()),
+            ]
+        );
+        let rendered = check("+]\n[[-]", &ParseOptions::default())[1].render("+]\n[[-]", "a.bf");
+        assert_eq!(rendered, "a.bf:2:1: error: '[' is never closed\n  |\n2 | [[-]\n  | ^");
+    }
+
+    #[test]
+    fn caret_under_the_character() {
+        assert_eq!(snippet("+\n é]", 5), "  |\n2 |  é]\n  |   ^");
+        // past the last line, e.g. at the end of a source ending in a newline
+        assert_eq!(snippet("+\n", 2), "  |\n2 | \n  | ^");
+    }
+
+    #[test]
+    fn suspicious_loops() {
+        let found = messages("+[]+[--]");
+        assert_eq!(found[0], (1, Severity::Warning, "empty loop never terminates once entered".to_string()));
+        assert_eq!(
+            found[1],
+            (4, Severity::Warning, "loop adding -2 only terminates if the cell starts as a multiple of 2".to_string())
+        );
+    }
+
+    #[test]
+    fn pointer_underflow() {
+        assert_eq!(messages(">+[-<+>]<"), vec![]);
+        assert_eq!(messages(">[-<<+>>]")[0].2, "pointer moves left of the first cell");
+        assert_eq!(messages(">+<<+")[0].0, 2);
+        // unknown after a scan loop
+        assert_eq!(messages("[>]<<"), vec![]);
+        // unbalanced loop
+        assert_eq!(messages("+[>-]<<"), vec![]);
+        assert_eq!(messages("+[<]"), vec![]);
+    }
+}
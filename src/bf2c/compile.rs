@@ -0,0 +1,96 @@
+//! Building generated C code with the system C compiler.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::error::Bf2cError;
+
+/// Compiles `code` with `cc` into an executable in `work_dir` and returns its
+/// path. The compiler's diagnostics go to stderr.
+pub fn compile(code: &str, cc: &str, work_dir: &Path) -> Result<PathBuf, Bf2cError> {
+    let c_file = work_dir.join("program.c");
+    let executable = work_dir.join("program");
+    fs::write(&c_file, code).map_err(|e| Bf2cError::io(&format!("write {}", c_file.display()), e))?;
+    let status = Command::new(cc)
+        .arg("-o")
+        .arg(&executable)
+        .arg(&c_file)
+        .status()
+        .map_err(|e| Bf2cError::Compile(format!("cannot run {}: {}", cc, e)))?;
+    if !status.success() {
+        return Err(Bf2cError::Compile(format!("{} failed to compile the generated code ({})", cc, status)));
+    }
+    Ok(executable)
+}
+
+/// An executable made by `build`.
+#[derive(Debug, Clone)]
+pub struct Build {
+    pub executable: PathBuf,
+    /// What the compiler wrote to stderr, usually warnings. Often empty.
+    pub diagnostics: String,
+}
+
+/// Compiles `code` with `cc` and the extra `flags` into the executable
+/// `output`, writing the C file to `work_dir`. The compiler's diagnostics
+/// are captured, and are part of the error if it fails.
+pub fn build(code: &str, cc: &str, flags: &[String], work_dir: &Path, output: &Path) -> Result<Build, Bf2cError> {
+    let c_file = work_dir.join("program.c");
+    fs::write(&c_file, code).map_err(|e| Bf2cError::io(&format!("write {}", c_file.display()), e))?;
+    // flags go last, where libraries like -lm have to be
+    let result = Command::new(cc)
+        .arg("-o")
+        .arg(output)
+        .arg(&c_file)
+        .args(flags)
+        .output()
+        .map_err(|e| Bf2cError::Compile(format!("cannot run {}: {}", cc, e)))?;
+    let diagnostics = String::from_utf8_lossy(&result.stderr).into_owned();
+    if !result.status.success() {
+        let mut message = format!("{} failed to compile the generated code ({})", cc, result.status);
+        if !diagnostics.trim().is_empty() {
+            message = format!("{}\n{}", message, diagnostics.trim_end());
+        }
+        return Err(Bf2cError::Compile(message));
+    }
+    Ok(Build { executable: output.to_path_buf(), diagnostics })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build, compile};
+    use std::process::Command;
+
+    #[test]
+    fn builds_an_executable() {
+        if Command::new("cc").arg("--version").output().is_err() {
+            return; // no C compiler available
+        }
+        let dir = std::env::temp_dir().join(format!("bf-compile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let status = compile("int main(void) { return 3; }\n", "cc", &dir).map(|exe| Command::new(exe).status());
+        let broken = compile("not C", "cc", &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(status.unwrap().unwrap().code(), Some(3));
+        assert!(broken.unwrap_err().to_string().starts_with("cc failed to compile"));
+    }
+
+    #[test]
+    fn builds_with_flags_and_captures_diagnostics() {
+        if Command::new("cc").arg("--version").output().is_err() {
+            return; // no C compiler available
+        }
+        let dir = std::env::temp_dir().join(format!("bf-build-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("three");
+        let flags = ["-O2".to_string(), "-DCODE=3".to_string()];
+        let built = build("int main(void) { return CODE; }\n", "cc", &flags, &dir, &output);
+        let status = built.as_ref().map(|built| Command::new(&built.executable).status());
+        let broken = build("int main(void) { return CODE; }\n", "cc", &[], &dir, &output);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(status.unwrap().unwrap().code(), Some(3));
+        let message = broken.unwrap_err().to_string();
+        assert!(message.starts_with("cc failed to compile") && message.contains("CODE"), "{}", message);
+    }
+}
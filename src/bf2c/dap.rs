@@ -0,0 +1,560 @@
+//! The interpreter's `Machine` behind the Debug Adapter Protocol, so editors
+//! like VS Code can launch a program, stop at breakpoints, step forwards and
+//! backwards, and show the tape as variables.
+//!
+//! The program shows as a single thread. Lines and columns are 1-based, columns
+//! count characters. The program reads the `input` string given to
+//! `launch`, and its output is sent as output events.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use super::bf2c::{parse_with_spans, ParseOptions};
+use super::check::line_column;
+use super::debugger::DEFAULT_JOURNAL_LEN;
+use super::error::Bf2cError;
+use super::interp::Machine;
+use super::profile::snippet;
+
+/// Steps executed between checks for a `pause` while the program runs.
+const SLICE_STEPS: u64 = 100_000;
+
+/// Variables reference of the scope with the pointer and step count.
+const MACHINE_SCOPE: u64 = 1;
+/// Variables reference of the scope with one variable per cell.
+const TAPE_SCOPE: u64 = 2;
+
+/// Serves one client on stdin and stdout until it disconnects.
+pub fn stdio(options: ParseOptions) -> Result<(), Bf2cError> {
+    // requests are read on their own thread so `pause` can interrupt a run
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        loop {
+            let message = read_message(&mut stdin);
+            let last = !matches!(message, Ok(Some(_)));
+            if sender.send(message).is_err() || last {
+                break;
+            }
+        }
+    });
+    let mut stdout = io::stdout().lock();
+    let mut adapter = Adapter::new(options);
+    while !adapter.is_done() {
+        let message = if adapter.is_running() {
+            match receiver.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        } else {
+            Some(receiver.recv().unwrap_or(Ok(None)))
+        };
+        let replies = match message {
+            Some(message) => match message? {
+                Some(message) => adapter.handle(&message),
+                // the client went away
+                None => return Ok(()),
+            },
+            None => adapter.run(SLICE_STEPS),
+        };
+        for reply in replies {
+            write_message(&mut stdout, &reply)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads one message, `None` at the end of the stream.
+pub fn read_message<R: BufRead>(reader: &mut R) -> Result<Option<Value>, Bf2cError> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(|e| Bf2cError::io("read from the client", e))? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse().map_err(|_| Bf2cError::Invalid(format!("invalid header '{}'", line)))?);
+        }
+    }
+    let length = length.ok_or_else(|| Bf2cError::Invalid("message without a Content-Length header".to_string()))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|e| Bf2cError::io("read from the client", e))?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| Bf2cError::Invalid(format!("invalid message: {}", e)))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Value) -> Result<(), Bf2cError> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| writer.flush())
+        .map_err(|e| Bf2cError::io("write to the client", e))
+}
+
+/// The launched program.
+struct Session {
+    path: PathBuf,
+    source: String,
+    machine: Machine,
+    input: io::Cursor<Vec<u8>>,
+    /// Output not sent to the client yet.
+    output: Vec<u8>,
+    stop_on_entry: bool,
+}
+
+/// The state of the adapter between messages.
+pub struct Adapter {
+    options: ParseOptions,
+    seq: u64,
+    session: Option<Session>,
+    /// Source offsets to stop before.
+    breakpoints: BTreeSet<usize>,
+    configured: bool,
+    started: bool,
+    running: bool,
+    /// Set by `continue`, whose first step ignores breakpoints so it always
+    /// makes progress.
+    resuming: bool,
+    done: bool,
+}
+
+impl Adapter {
+    pub fn new(options: ParseOptions) -> Self {
+        Adapter {
+            options,
+            seq: 0,
+            session: None,
+            breakpoints: BTreeSet::new(),
+            configured: false,
+            started: false,
+            running: false,
+            resuming: false,
+            done: false,
+        }
+    }
+
+    /// Whether the program is running, and `run` should be called until it
+    /// stops.
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Whether the client disconnected.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Handles a request from the client, returning the response and the
+    /// events that follow it.
+    pub fn handle(&mut self, request: &Value) -> Vec<Value> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+        let mut events = Vec::new();
+        let result = match command {
+            "initialize" => {
+                events.push(event("initialized", json!({})));
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsStepBack": true,
+                    "supportsSetVariable": true,
+                }))
+            }
+            "launch" => self.launch(arguments).map(|()| {
+                events = self.start();
+                Value::Null
+            }),
+            "configurationDone" => {
+                self.configured = true;
+                events = self.start();
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "threads" => Ok(json!({ "threads": [{ "id": 1, "name": "main" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(self.scopes()),
+            "variables" => self.variables(arguments),
+            "setVariable" => self.set_variable(arguments),
+            "continue" => {
+                self.running = self.session.is_some();
+                self.resuming = true;
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            // there are no calls, so stepping in or out is a single step too
+            "next" | "stepIn" | "stepOut" => {
+                events = self.step();
+                Ok(Value::Null)
+            }
+            "stepBack" => {
+                events = self.step_back(false);
+                Ok(Value::Null)
+            }
+            "reverseContinue" => {
+                events = self.step_back(true);
+                Ok(Value::Null)
+            }
+            "pause" => {
+                if self.running {
+                    self.running = false;
+                    events.push(stopped("pause", None));
+                }
+                Ok(Value::Null)
+            }
+            "disconnect" | "terminate" => {
+                self.done = true;
+                Ok(Value::Null)
+            }
+            _ => Err(format!("unsupported request '{}'", command)),
+        };
+        let mut replies = vec![response(request, result)];
+        replies.append(&mut events);
+        self.sequence(replies)
+    }
+
+    /// Runs at most `steps` steps of a running program, returning the events
+    /// for where it stopped, if it did.
+    pub fn run(&mut self, steps: u64) -> Vec<Value> {
+        let Some(session) = self.session.as_mut() else {
+            self.running = false;
+            return Vec::new();
+        };
+        let mut stop = None;
+        for _ in 0..steps {
+            if session.machine.is_finished() {
+                break;
+            }
+            if !self.resuming && at_breakpoint(&session.machine, &self.breakpoints) {
+                stop = Some(("breakpoint", None));
+                break;
+            }
+            self.resuming = false;
+            if let Err(e) = session.machine.step(&mut session.input, &mut session.output) {
+                stop = Some(("exception", Some(e.to_string())));
+                break;
+            }
+        }
+        let finished = session.machine.is_finished();
+        let mut events = self.output();
+        if finished {
+            events.append(&mut self.exit());
+        } else if let Some((reason, text)) = stop {
+            self.running = false;
+            events.push(stopped(reason, text));
+        }
+        self.sequence(events)
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<(), String> {
+        let path = arguments["program"].as_str().ok_or("missing 'program' to launch")?;
+        let source = fs::read_to_string(path).map_err(|e| format!("cannot read '{}': {}", path, e))?;
+        let statements = arguments["statements"].as_bool().unwrap_or(false);
+        let mut machine = Machine::from_source(&source, statements, &self.options).map_err(|e| e.to_string())?;
+        machine.set_journal(DEFAULT_JOURNAL_LEN);
+        let input = arguments["input"].as_str().unwrap_or_default().as_bytes().to_vec();
+        let stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.session = Some(Session {
+            path: PathBuf::from(path),
+            source,
+            machine,
+            input: io::Cursor::new(input),
+            output: Vec::new(),
+            stop_on_entry,
+        });
+        Ok(())
+    }
+
+    /// Starts the program once it is launched and the client has set its
+    /// breakpoints.
+    fn start(&mut self) -> Vec<Value> {
+        let Some(session) = self.session.as_ref() else {
+            return Vec::new();
+        };
+        if !self.configured || self.started {
+            return Vec::new();
+        }
+        self.started = true;
+        if session.stop_on_entry {
+            return vec![stopped("entry", None)];
+        }
+        self.running = true;
+        Vec::new()
+    }
+
+    fn step(&mut self) -> Vec<Value> {
+        let Some(session) = self.session.as_mut() else {
+            return Vec::new();
+        };
+        let result = session.machine.step(&mut session.input, &mut session.output);
+        let finished = session.machine.is_finished();
+        let mut events = self.output();
+        match result {
+            Err(e) => events.push(stopped("exception", Some(e.to_string()))),
+            Ok(()) if finished => events.append(&mut self.exit()),
+            Ok(()) => events.push(stopped("step", None)),
+        }
+        events
+    }
+
+    /// Undoes one step, or steps back to the previous breakpoint if
+    /// `to_breakpoint` is set.
+    fn step_back(&mut self, to_breakpoint: bool) -> Vec<Value> {
+        let Some(session) = self.session.as_mut() else {
+            return Vec::new();
+        };
+        let mut moved = session.machine.step_back();
+        while to_breakpoint && moved && !at_breakpoint(&session.machine, &self.breakpoints) {
+            moved = session.machine.step_back();
+        }
+        let reason = if to_breakpoint && moved { "breakpoint" } else { "step" };
+        vec![stopped(reason, None)]
+    }
+
+    /// Resolves each requested line and column to the first instruction at
+    /// or after it on that line.
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments["source"]["path"].as_str().ok_or("missing source path")?;
+        let source = match &self.session {
+            Some(session) if session.path == Path::new(path) => session.source.clone(),
+            _ => fs::read_to_string(path).map_err(|e| format!("cannot read '{}': {}", path, e))?,
+        };
+        let (_, spans) = parse_with_spans(&source, false, &self.options).expect("parsing without verification cannot fail");
+        self.breakpoints.clear();
+        let requested = arguments["breakpoints"].as_array().map_or(&[][..], Vec::as_slice);
+        let mut breakpoints = Vec::new();
+        for breakpoint in requested {
+            let line = breakpoint["line"].as_u64().unwrap_or(1) as usize;
+            let column = breakpoint["column"].as_u64().unwrap_or(1) as usize;
+            let found = offset(&source, line, column).and_then(|(start, line_end)| {
+                spans.iter().map(|span| span.start).find(|&offset| offset >= start && offset < line_end)
+            });
+            breakpoints.push(match found {
+                Some(offset) => {
+                    self.breakpoints.insert(offset);
+                    let (line, column) = line_column(&source, offset);
+                    json!({ "verified": true, "line": line, "column": column })
+                }
+                None => json!({ "verified": false, "line": line, "message": "no instruction on this line" }),
+            });
+        }
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    /// The next step as the only frame.
+    fn stack_trace(&self) -> Value {
+        let Some((session, span)) = self.session.as_ref().and_then(|session| Some((session, session.machine.span()?))) else {
+            return json!({ "stackFrames": [], "totalFrames": 0 });
+        };
+        let (line, column) = line_column(&session.source, span.start);
+        let (end_line, end_column) = line_column(&session.source, span.end);
+        let name = session.path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        json!({
+            "stackFrames": [{
+                "id": 1,
+                "name": snippet(&session.source[span]),
+                "source": { "name": name, "path": session.path },
+                "line": line,
+                "column": column,
+                "endLine": end_line,
+                "endColumn": end_column,
+            }],
+            "totalFrames": 1,
+        })
+    }
+
+    fn scopes(&self) -> Value {
+        let cells = self.session.as_ref().map_or(0, |session| session.machine.tape.cells.len());
+        json!({ "scopes": [
+            { "name": "Machine", "variablesReference": MACHINE_SCOPE, "expensive": false },
+            { "name": "Tape", "variablesReference": TAPE_SCOPE, "indexedVariables": cells, "expensive": false },
+        ] })
+    }
+
+    /// The pointer and step count, or a page of cells. Without paging the
+    /// cells up to the pointer or the last nonzero cell are listed.
+    fn variables(&self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session.as_ref().ok_or("no program is running")?;
+        let machine = &session.machine;
+        let tape = &machine.tape;
+        let variables: Vec<Value> = match arguments["variablesReference"].as_u64() {
+            Some(MACHINE_SCOPE) => vec![
+                variable("ptr", tape.ptr),
+                variable("cell", tape.cells[tape.ptr]),
+                variable("steps", machine.steps),
+            ],
+            Some(TAPE_SCOPE) => {
+                let used = tape.cells.iter().rposition(|&cell| cell != 0).map_or(tape.ptr, |last| last.max(tape.ptr)) + 1;
+                let start = arguments["start"].as_u64().map_or(0, |start| start as usize).min(tape.cells.len());
+                let count = arguments["count"].as_u64().map_or(used.saturating_sub(start), |count| count as usize);
+                let end = start.saturating_add(count).min(tape.cells.len());
+                (start..end).map(|cell| variable(&format!("[{}]", cell), tape.cells[cell])).collect()
+            }
+            _ => return Err("unknown variables reference".to_string()),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    /// Writes a cell of the tape.
+    fn set_variable(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session.as_mut().ok_or("no program is running")?;
+        let name = arguments["name"].as_str().unwrap_or_default();
+        let cell = name
+            .strip_prefix('[')
+            .and_then(|name| name.strip_suffix(']'))
+            .and_then(|cell| cell.parse::<usize>().ok())
+            .filter(|_| arguments["variablesReference"].as_u64() == Some(TAPE_SCOPE))
+            .ok_or_else(|| format!("'{}' cannot be changed, only tape cells can", name))?;
+        let value = arguments["value"].as_str().unwrap_or_default();
+        let value: u8 = value.trim().parse().map_err(|_| format!("'{}' is not a cell value", value))?;
+        let slot = session.machine.tape.cells.get_mut(cell).ok_or_else(|| format!("cell {} is outside the tape", cell))?;
+        *slot = value;
+        Ok(json!({ "value": value.to_string() }))
+    }
+
+    /// An output event with what the program wrote since the last one.
+    fn output(&mut self) -> Vec<Value> {
+        let Some(session) = self.session.as_mut().filter(|session| !session.output.is_empty()) else {
+            return Vec::new();
+        };
+        let text = String::from_utf8_lossy(&session.output).into_owned();
+        session.output.clear();
+        vec![event("output", json!({ "category": "stdout", "output": text }))]
+    }
+
+    fn exit(&mut self) -> Vec<Value> {
+        self.running = false;
+        vec![event("exited", json!({ "exitCode": 0 })), event("terminated", json!({}))]
+    }
+
+    /// Numbers `messages` in the order they are sent.
+    fn sequence(&mut self, mut messages: Vec<Value>) -> Vec<Value> {
+        for message in &mut messages {
+            self.seq += 1;
+            message["seq"] = json!(self.seq);
+        }
+        messages
+    }
+}
+
+fn stopped(reason: &str, text: Option<String>) -> Value {
+    let mut body = json!({ "reason": reason, "threadId": 1, "allThreadsStopped": true });
+    if let Some(text) = text {
+        body["text"] = Value::String(text);
+    }
+    event("stopped", body)
+}
+
+fn response(request: &Value, result: Result<Value, String>) -> Value {
+    let mut response = json!({
+        "type": "response",
+        "request_seq": request["seq"],
+        "command": request["command"],
+        "success": result.is_ok(),
+    });
+    match result {
+        Ok(Value::Null) => {}
+        Ok(body) => response["body"] = body,
+        Err(message) => response["message"] = Value::String(message),
+    }
+    response
+}
+
+fn event(event: &str, body: Value) -> Value {
+    json!({ "type": "event", "event": event, "body": body })
+}
+
+/// Whether the next step covers a breakpoint.
+fn at_breakpoint(machine: &Machine, breakpoints: &BTreeSet<usize>) -> bool {
+    match machine.span() {
+        Some(span) => breakpoints.range(span).next().is_some(),
+        None => false,
+    }
+}
+
+fn variable(name: &str, value: impl ToString) -> Value {
+    json!({ "name": name, "value": value.to_string(), "variablesReference": 0 })
+}
+
+/// Byte offset of 1-based `line` and `column` in `source`, and the end of
+/// that line. Columns past the end of the line are clamped to it.
+fn offset(source: &str, line: usize, column: usize) -> Option<(usize, usize)> {
+    let start: usize = source.split_inclusive('\n').take(line.checked_sub(1)?).map(str::len).sum();
+    if start >= source.len() && line > 1 {
+        return None;
+    }
+    let text = source[start..].split('\n').next().unwrap_or_default();
+    let within = text.char_indices().nth(column.saturating_sub(1)).map_or(text.len(), |(index, _)| index);
+    Some((start + within, start + text.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::super::bf2c::ParseOptions;
+    use super::{read_message, write_message, Adapter};
+
+    fn request(adapter: &mut Adapter, command: &str, arguments: Value) -> Vec<Value> {
+        adapter.handle(&json!({ "seq": 1, "type": "request", "command": command, "arguments": arguments }))
+    }
+
+    /// Kinds of the events in `messages`.
+    fn events(messages: &[Value]) -> Vec<&str> {
+        messages.iter().filter(|message| message["type"] == "event").map(|message| message["event"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_shows_the_tape() {
+        let path = std::env::temp_dir().join(format!("bf-dap-{}.bf", std::process::id()));
+        std::fs::write(&path, "+++++++[>+++++++<-]\n  >.+.").unwrap();
+        let mut adapter = Adapter::new(ParseOptions::default());
+        assert_eq!(events(&request(&mut adapter, "initialize", json!({}))), ["initialized"]);
+        let launched = request(&mut adapter, "launch", json!({ "program": path }));
+        assert_eq!(launched[0]["success"], true);
+        let set = request(&mut adapter, "setBreakpoints", json!({ "source": { "path": path }, "breakpoints": [{ "line": 2 }, { "line": 3 }] }));
+        assert_eq!(set[0]["body"]["breakpoints"][0], json!({ "verified": true, "line": 2, "column": 3 }));
+        assert_eq!(set[0]["body"]["breakpoints"][1]["verified"], false);
+        request(&mut adapter, "configurationDone", json!({}));
+        assert!(adapter.is_running());
+
+        let stopped = adapter.run(1000);
+        assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+        let frames = request(&mut adapter, "stackTrace", json!({ "threadId": 1 }));
+        assert_eq!((&frames[0]["body"]["stackFrames"][0]["line"], &frames[0]["body"]["stackFrames"][0]["column"]), (&json!(2), &json!(3)));
+        let cells = request(&mut adapter, "variables", json!({ "variablesReference": 2 }));
+        assert_eq!(cells[0]["body"]["variables"], json!([
+            { "name": "[0]", "value": "0", "variablesReference": 0 },
+            { "name": "[1]", "value": "49", "variablesReference": 0 },
+        ]));
+
+        request(&mut adapter, "next", json!({ "threadId": 1 }));
+        request(&mut adapter, "setVariable", json!({ "variablesReference": 2, "name": "[1]", "value": "64" }));
+        let output = request(&mut adapter, "next", json!({ "threadId": 1 }));
+        assert_eq!(output[1]["body"]["output"], "@");
+        let back = request(&mut adapter, "stepBack", json!({ "threadId": 1 }));
+        assert_eq!(events(&back), ["stopped"]);
+
+        request(&mut adapter, "continue", json!({ "threadId": 1 }));
+        let finished = adapter.run(1000);
+        assert_eq!(events(&finished), ["output", "exited", "terminated"]);
+        assert_eq!(finished[0]["body"]["output"], "@A");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn frames_messages() {
+        let mut buf = Vec::new();
+        write_message(&mut buf, &json!({ "seq": 1 })).unwrap();
+        assert_eq!(buf, b"Content-Length: 9\r\n\r\n{\"seq\":1}");
+        assert_eq!(read_message(&mut &buf[..]).unwrap(), Some(json!({ "seq": 1 })));
+        assert_eq!(read_message(&mut &b""[..]).unwrap(), None);
+        assert!(read_message(&mut &b"Content-Type: x\r\n\r\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,429 @@
+//! Interactive debugger built on the interpreter's `Machine`.
+
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::bf2c::ParseOptions;
+use super::error::Bf2cError;
+use super::interp::Machine;
+use super::watch::WatchKind;
+use super::snapshot::Snapshot;
+
+const HELP: &str = "\
+commands:
+  step [n]          (s)  execute the next n steps (default 1)
+  back [n]          (rs) undo the last n steps (default 1)
+  continue          (c)  run until a breakpoint or the end of the program
+  break <offset>    (b)  stop before the step at a source offset
+  delete <offset>   (d)  remove a breakpoint
+  breakpoints            list breakpoints
+  watch <cell> [kind]    stop when a cell is accessed; kind is r, w (default) or rw
+  unwatch <cell>         remove a watchpoint
+  watchpoints            list watchpoints
+  tape [cell] [n]   (t)  show n cells from cell (default: around the pointer)
+  set <cell> <value>     write a value into a cell
+  ptr                    print the data pointer
+  dump <file>            save a tape snapshot to resume from later
+  heatmap [file]         show cell activity, or save it (as HTML for .html)
+  where             (w)  show the next step in the source
+  help              (h)  show this message
+  quit              (q)  leave the debugger
+";
+
+/// Number of cells shown on each side of the pointer by `tape`.
+const TAPE_WINDOW: usize = 4;
+
+/// Steps that can be undone with `back` unless configured otherwise.
+pub const DEFAULT_JOURNAL_LEN: usize = 100_000;
+
+pub struct Debugger<'a> {
+    source: &'a str,
+    machine: Machine,
+    breakpoints: BTreeSet<usize>,
+    /// Program input, read from the command stream when unset.
+    input: Option<Box<dyn Read + 'a>>,
+    /// Program output, interleaved with the debugger's when unset.
+    output: Option<Box<dyn Write + 'a>>,
+}
+
+impl<'a> Debugger<'a> {
+    /// Debugs `source` one instruction at a time, or one IR statement at a
+    /// time if `statements` is set.
+    pub fn new(source: &'a str, statements: bool, options: &ParseOptions) -> Result<Self, Bf2cError> {
+        let mut machine = Machine::from_source(source, statements, options)?;
+        machine.set_journal(DEFAULT_JOURNAL_LEN);
+        machine.set_activity();
+        Ok(Debugger { source, machine, breakpoints: BTreeSet::new(), input: None, output: None })
+    }
+
+    /// Continues from a saved tape snapshot instead of the start.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), Bf2cError> {
+        self.machine.restore(snapshot)
+    }
+
+    /// Keeps at most `len` steps for `back`. Zero disables stepping back.
+    pub fn set_journal_len(&mut self, len: usize) {
+        self.machine.set_journal(len);
+    }
+
+    /// Feeds the program from `input` instead of the command stream.
+    pub fn set_input(&mut self, input: Box<dyn Read + 'a>) {
+        self.input = Some(input);
+    }
+
+    /// Sends program output to `output` instead of the debugger's output.
+    pub fn set_output(&mut self, output: Box<dyn Write + 'a>) {
+        self.output = Some(output);
+    }
+
+    /// Reads commands from `input` until `quit` or end of input. Unless
+    /// redirected, program input is read from the same stream and program
+    /// output is interleaved with the debugger's on `out`.
+    pub fn repl<I: BufRead, W: Write>(&mut self, input: &mut I, out: &mut W) -> Result<(), Bf2cError> {
+        self.location(out).map_err(|e| Bf2cError::io("write output", e))?;
+        loop {
+            write!(out, "(bf) ").and_then(|_| out.flush()).map_err(|e| Bf2cError::io("write output", e))?;
+            let mut line = String::new();
+            if input.read_line(&mut line).map_err(|e| Bf2cError::io("read commands", e))? == 0 {
+                return Ok(());
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let Some((&command, args)) = words.split_first() else {
+                continue;
+            };
+            let result = match command {
+                "step" | "s" => optional(args.first(), 1).and_then(|n| self.step(n, input, out)),
+                "back" | "rs" => optional(args.first(), 1).and_then(|n| self.back(n, out)),
+                "continue" | "c" => self.continue_(input, out),
+                "break" | "b" => self.set_breakpoint(args, out),
+                "delete" | "d" => self.delete_breakpoint(args, out),
+                "breakpoints" => self.list_breakpoints(out),
+                "watch" => self.set_watch(args, out),
+                "unwatch" => self.delete_watch(args, out),
+                "watchpoints" => self.list_watches(out),
+                "tape" | "t" => self.print_tape(args, out),
+                "set" => self.set_cell(args),
+                "dump" => self.dump(args, out),
+                "heatmap" => self.heatmap(args, out),
+                "ptr" => writeln!(out, "ptr = {}", self.machine.tape.ptr).map_err(|e| e.to_string()),
+                "where" | "w" => self.where_(out),
+                "help" | "h" => write!(out, "{}", HELP).map_err(|e| e.to_string()),
+                "quit" | "q" => return Ok(()),
+                _ => Err(format!("unknown command '{}', try 'help'", command)),
+            };
+            if let Err(e) = result {
+                writeln!(out, "error: {}", e).map_err(|e| Bf2cError::io("write output", e))?;
+            }
+            if let Some(output) = self.output.as_mut() {
+                output.flush().map_err(|e| Bf2cError::io("write program output", e))?;
+            }
+        }
+    }
+
+    fn step_once<I: BufRead, W: Write>(&mut self, commands: &mut I, out: &mut W) -> Result<(), String> {
+        let mut input: &mut dyn Read = match self.input.as_deref_mut() {
+            Some(input) => input,
+            None => commands,
+        };
+        let mut output: &mut dyn Write = match self.output.as_deref_mut() {
+            Some(output) => output,
+            None => out,
+        };
+        Ok(self.machine.step(&mut input, &mut output)?)
+    }
+
+    fn step<I: BufRead, W: Write>(&mut self, count: usize, input: &mut I, out: &mut W) -> Result<(), String> {
+        for _ in 0..count {
+            if self.machine.is_finished() {
+                break;
+            }
+            self.step_once(input, out)?;
+            if self.report_watch_hit(out)? {
+                break;
+            }
+        }
+        self.where_(out)
+    }
+
+    /// Prints the watchpoint hit by the last step, if any.
+    fn report_watch_hit<W: Write>(&self, out: &mut W) -> Result<bool, String> {
+        match self.machine.watch_hit() {
+            Some(hit) => writeln!(out, "watchpoint hit: {}", hit).map(|_| true).map_err(|e| e.to_string()),
+            None => Ok(false),
+        }
+    }
+
+    fn back<W: Write>(&mut self, count: usize, out: &mut W) -> Result<(), String> {
+        for undone in 0..count {
+            if !self.machine.step_back() {
+                let reason = match self.machine.journal() {
+                    Some(journal) if journal.capacity() > 0 && self.machine.steps > 0 => {
+                        "older steps are no longer in the journal"
+                    }
+                    Some(journal) if journal.capacity() > 0 => "at the start of the program",
+                    _ => "stepping back is disabled",
+                };
+                writeln!(out, "stepped back {} steps, {}", undone, reason).map_err(|e| e.to_string())?;
+                break;
+            }
+        }
+        self.where_(out)
+    }
+
+    fn continue_<I: BufRead, W: Write>(&mut self, input: &mut I, out: &mut W) -> Result<(), String> {
+        // always make progress, even when sitting on a breakpoint
+        self.step_once(input, out)?;
+        while !self.machine.is_finished() && !self.at_breakpoint() && self.machine.watch_hit().is_none() {
+            self.step_once(input, out)?;
+        }
+        if self.report_watch_hit(out)? {
+            return self.where_(out);
+        }
+        if let Some(span) = self.machine.span() {
+            writeln!(out, "breakpoint hit at offset {}", span.start).map_err(|e| e.to_string())?;
+        }
+        self.where_(out)
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        match self.machine.span() {
+            Some(span) => self.breakpoints.range(span).next().is_some(),
+            None => false,
+        }
+    }
+
+    fn set_breakpoint<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<(), String> {
+        let offset = required(args.first())?;
+        if offset >= self.source.len() {
+            return Err(format!("offset {} is outside the program", offset));
+        }
+        self.breakpoints.insert(offset);
+        writeln!(out, "breakpoint set at offset {}", offset).map_err(|e| e.to_string())
+    }
+
+    fn delete_breakpoint<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<(), String> {
+        let offset = required(args.first())?;
+        if !self.breakpoints.remove(&offset) {
+            return Err(format!("no breakpoint at offset {}", offset));
+        }
+        writeln!(out, "breakpoint at offset {} deleted", offset).map_err(|e| e.to_string())
+    }
+
+    fn list_breakpoints<W: Write>(&self, out: &mut W) -> Result<(), String> {
+        if self.breakpoints.is_empty() {
+            return writeln!(out, "no breakpoints").map_err(|e| e.to_string());
+        }
+        for offset in &self.breakpoints {
+            writeln!(out, "breakpoint at offset {}", offset).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn set_watch<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<(), String> {
+        let cell: usize = required(args.first())?;
+        let kind: WatchKind = match args.get(1) {
+            Some(kind) => kind.parse()?,
+            None => WatchKind::Write,
+        };
+        if cell >= self.machine.tape.cells.len() {
+            return Err(format!("cell {} is outside the tape", cell));
+        }
+        self.machine.set_watch(cell, kind);
+        writeln!(out, "watching {}s of cell {}", kind, cell).map_err(|e| e.to_string())
+    }
+
+    fn delete_watch<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<(), String> {
+        let cell = required(args.first())?;
+        if !self.machine.remove_watch(cell) {
+            return Err(format!("no watchpoint on cell {}", cell));
+        }
+        writeln!(out, "watchpoint on cell {} deleted", cell).map_err(|e| e.to_string())
+    }
+
+    fn list_watches<W: Write>(&self, out: &mut W) -> Result<(), String> {
+        if self.machine.watches().is_empty() {
+            return writeln!(out, "no watchpoints").map_err(|e| e.to_string());
+        }
+        for (cell, kind) in self.machine.watches() {
+            writeln!(out, "watching {}s of cell {}", kind, cell).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn print_tape<W: Write>(&self, args: &[&str], out: &mut W) -> Result<(), String> {
+        let tape = &self.machine.tape;
+        let start = optional(args.first(), tape.ptr.saturating_sub(TAPE_WINDOW))?;
+        let count = optional(args.get(1), 2 * TAPE_WINDOW + 1)?;
+        let end = start.saturating_add(count).min(tape.cells.len());
+        for cell in start..end {
+            let marker = if cell == tape.ptr { " <- ptr" } else { "" };
+            writeln!(out, "{:>6}: {:>3}{}", cell, tape.cells[cell], marker).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn set_cell(&mut self, args: &[&str]) -> Result<(), String> {
+        let cell: usize = required(args.first())?;
+        let value: u8 = required(args.get(1))?;
+        match self.machine.tape.cells.get_mut(cell) {
+            Some(slot) => *slot = value,
+            None => return Err(format!("cell {} is outside the tape", cell)),
+        }
+        Ok(())
+    }
+
+    fn dump<W: Write>(&self, args: &[&str], out: &mut W) -> Result<(), String> {
+        let path = args.first().ok_or("missing argument")?;
+        let mut file = File::create(path).map_err(|e| format!("cannot create {}: {}", path, e))?;
+        self.machine.snapshot().write(&mut file)?;
+        writeln!(out, "tape saved to {}", path).map_err(|e| e.to_string())
+    }
+
+    fn heatmap<W: Write>(&self, args: &[&str], out: &mut W) -> Result<(), String> {
+        let activity = self.machine.activity().ok_or("cell activity is not recorded")?;
+        match args.first() {
+            Some(path) => {
+                let text = activity.render_for(Path::new(path), &self.machine.tape);
+                fs::write(path, text).map_err(|e| format!("cannot write {}: {}", path, e))?;
+                writeln!(out, "heatmap saved to {}", path).map_err(|e| e.to_string())
+            }
+            None => write!(out, "{}", activity.ascii()).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn where_<W: Write>(&self, out: &mut W) -> Result<(), String> {
+        self.location(out).map_err(|e| e.to_string())
+    }
+
+    fn location<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        match self.machine.span() {
+            Some(span) => writeln!(out, "offset {}: {}", span.start, &self.source[span.clone()]),
+            None => writeln!(out, "program finished after {} steps", self.machine.steps),
+        }
+    }
+}
+
+fn required<T: FromStr>(arg: Option<&&str>) -> Result<T, String> {
+    match arg {
+        Some(arg) => arg.parse().map_err(|_| format!("invalid number '{}'", arg)),
+        None => Err("missing argument".to_string()),
+    }
+}
+
+fn optional<T: FromStr>(arg: Option<&&str>, default: T) -> Result<T, String> {
+    match arg {
+        Some(_) => required(arg),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bf2c::ParseOptions;
+    use super::super::snapshot::Snapshot;
+    use super::Debugger;
+    use std::fs::File;
+
+    fn session(source: &str, statements: bool, commands: &str) -> String {
+        let mut debugger = Debugger::new(source, statements, &ParseOptions::default()).unwrap();
+        let mut out = Vec::new();
+        debugger.repl(&mut commands.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn step_and_inspect() {
+        let out = session("++>+", false, "step 3\nptr\ntape 0 2\n");
+        assert!(out.contains("offset 3: +"));
+        assert!(out.contains("ptr = 1"));
+        assert!(out.contains("     0:   2\n     1:   0 <- ptr\n"));
+    }
+
+    #[test]
+    fn breakpoint_inside_coalesced_statement() {
+        let out = session("+++ [-] >+", true, "break 1\nbreak 8\ncontinue\nc\n");
+        assert!(out.starts_with("offset 0: +++\n"));
+        assert!(out.contains("breakpoint hit at offset 8\noffset 8: >"));
+        assert!(out.contains("program finished after 4 steps"));
+    }
+
+    #[test]
+    fn modify_cells() {
+        let out = session("[.]", false, "set 0 65\ns\ns\ntape 0 1\n");
+        assert!(out.contains("offset 1: ."));
+        assert!(out.contains("A"));
+        assert!(out.contains("     0:  65 <- ptr"));
+    }
+
+    #[test]
+    fn reports_bad_commands() {
+        let out = session("+", false, "frobnicate\nbreak 99\nset 0\n");
+        assert!(out.contains("error: unknown command 'frobnicate'"));
+        assert!(out.contains("error: offset 99 is outside the program"));
+        assert!(out.contains("error: missing argument"));
+    }
+
+    #[test]
+    fn redirected_program_io() {
+        let mut program_output = Vec::new();
+        let mut debugger = Debugger::new(",.", false, &ParseOptions::default()).unwrap();
+        debugger.set_input(Box::new(&b"x"[..]));
+        debugger.set_output(Box::new(&mut program_output));
+        let mut out = Vec::new();
+        debugger.repl(&mut "continue\n".as_bytes(), &mut out).unwrap();
+        drop(debugger);
+        assert_eq!(program_output, b"x");
+        assert!(!String::from_utf8(out).unwrap().contains('x'));
+    }
+
+    #[test]
+    fn dump_and_restore() {
+        let path = std::env::temp_dir().join(format!("bf-debugger-dump-{}", std::process::id()));
+        let commands = format!("s 3\ndump {}\n", path.display());
+        session("++>+++", false, &commands);
+
+        let mut debugger = Debugger::new("++>+++", false, &ParseOptions::default()).unwrap();
+        let snapshot = Snapshot::read(&mut File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        debugger.restore(snapshot).unwrap();
+        let mut out = Vec::new();
+        debugger.repl(&mut "c\ntape 0 2\n".as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("offset 3: +"));
+        assert!(out.contains("     0:   2\n     1:   3 <- ptr\n"));
+    }
+
+    #[test]
+    fn step_back() {
+        let out = session("+>+++<-", false, "s 5\nback 2\ntape 0 2\nrs 9\n");
+        assert!(out.contains("offset 3: +\n"));
+        assert!(out.contains("     0:   1\n     1:   1 <- ptr\n"));
+        assert!(out.contains("stepped back 3 steps, at the start of the program\noffset 0: +"));
+
+        let mut debugger = Debugger::new("+++", false, &ParseOptions::default()).unwrap();
+        debugger.set_journal_len(1);
+        let mut out = Vec::new();
+        debugger.repl(&mut "s 3\nback 2\n".as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("stepped back 1 steps, older steps are no longer in the journal\noffset 2: +"));
+    }
+
+    #[test]
+    fn heatmap() {
+        let out = session("++>+<", false, "c\nheatmap\n");
+        assert!(out.contains("2 cells touched, cells 0..2\n     0 |@*|\n"));
+    }
+
+    #[test]
+    fn watchpoints() {
+        let out = session("+>+++[<++>-]<.", true, "watch 0\nwatch 1 r\nwatchpoints\nc\nc\nc\nunwatch 1\nc\n");
+        assert!(out.contains("watching writes of cell 0\nwatching reads of cell 1\n"));
+        assert!(out.contains("watchpoint hit: cell 0 written at offset 0: 0 -> 1\noffset 1: >"));
+        assert!(out.contains("watchpoint hit: cell 1 read at offset 2: 0\noffset 5: ["));
+        assert!(out.contains("watchpoint hit: cell 0 written at offset 5: 1 -> 7\noffset 12: <"));
+        assert!(out.contains("program finished"));
+    }
+}
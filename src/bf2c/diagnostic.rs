@@ -0,0 +1,276 @@
+//! Diagnostics in a stable, machine-readable form, for editors and CI.
+//!
+//! `check` reports its findings as `Diagnostic`s, and the errors of the
+//! other phases convert into them, so an integration can handle everything
+//! the same way instead of parsing the rendered text.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::{self, Vec};
+use core::fmt;
+use core::ops::Deref;
+use core::slice;
+
+use super::bf2c::{ParseError, Span};
+use super::check::{line_column, snippet};
+use super::error::Bf2cError;
+
+/// What a diagnostic is about. The names returned by `as_str` are stable
+/// and safe to match on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+#[non_exhaustive]
+pub enum Code {
+    /// A `]` without a matching `[`.
+    UnmatchedClose,
+    /// A `[` that is never closed.
+    UnclosedOpen,
+    /// A loop with an empty body.
+    EmptyLoop,
+    /// A loop that only reaches zero from some starting values.
+    LoopMayNotTerminate,
+    /// The pointer moves left of the first cell.
+    PointerUnderflow,
+    /// See `Bf2cError::Invalid`.
+    Invalid,
+    /// See `Bf2cError::Runtime`.
+    Runtime,
+    /// See `Bf2cError::Limit`.
+    Limit,
+    /// See `Bf2cError::Compile`.
+    Compile,
+    /// See `Bf2cError::Io`.
+    Io,
+}
+
+impl Code {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Code::UnmatchedClose => "unmatched-close",
+            Code::UnclosedOpen => "unclosed-open",
+            Code::EmptyLoop => "empty-loop",
+            Code::LoopMayNotTerminate => "loop-may-not-terminate",
+            Code::PointerUnderflow => "pointer-underflow",
+            Code::Invalid => "invalid",
+            Code::Runtime => "runtime",
+            Code::Limit => "limit",
+            Code::Compile => "compile",
+            Code::Io => "io",
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostic {
+    pub code: Code,
+    pub severity: Severity,
+    pub message: String,
+    /// The source the diagnostic points at. Errors that are not about a
+    /// place in the source, like failing to write the output, have none.
+    pub primary_span: Option<Span>,
+    /// Further explanations, one sentence each.
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(code: Code, severity: Severity, message: &str) -> Self {
+        Diagnostic { code, severity, message: message.to_string(), primary_span: None, notes: Vec::new() }
+    }
+
+    pub fn with_span(self, span: Span) -> Self {
+        Diagnostic { primary_span: Some(span), ..self }
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// Byte offset in the source the diagnostic points at.
+    pub fn offset(&self) -> Option<usize> {
+        self.primary_span.map(|span| span.start)
+    }
+
+    /// The same diagnostic at `offset` in `source`, for diagnostics found in
+    /// text assembled from several files.
+    pub fn relocate(&self, source: &str, offset: usize) -> Diagnostic {
+        let span = self.primary_span.map(|span| {
+            let (line, column) = line_column(source, offset);
+            Span { start: offset, end: offset + span.end - span.start, line, column }
+        });
+        Diagnostic { primary_span: span, ..self.clone() }
+    }
+
+    /// `name:line:column: severity: message`, followed by the source line
+    /// with a caret under the offending character and the notes.
+    pub fn render(&self, source: &str, name: &str) -> String {
+        let mut out = match self.offset() {
+            Some(offset) => {
+                let (line, column) = line_column(source, offset);
+                format!("{}:{}:{}: {}: {}\n{}", name, line, column, self.severity, self.message, snippet(source, offset))
+            }
+            None => format!("{}: {}: {}", name, self.severity, self.message),
+        };
+        for note in &self.notes {
+            out.push_str(&format!("\n  = note: {}", note));
+        }
+        out
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(e: ParseError) -> Self {
+        let (code, offset, line, column) = match e {
+            ParseError::UnmatchedClose { offset, line, column } => (Code::UnmatchedClose, offset, line, column),
+            ParseError::UnclosedOpen { offset, line, column } => (Code::UnclosedOpen, offset, line, column),
+        };
+        // the span covers the first byte of the bracket
+        Diagnostic::new(code, Severity::Error, &e.to_string()).with_span(Span { start: offset, end: offset + 1, line, column })
+    }
+}
+
+impl From<&Bf2cError> for Diagnostic {
+    fn from(e: &Bf2cError) -> Self {
+        let code = match e {
+            Bf2cError::Parse(e) => return Diagnostic::from(*e),
+            Bf2cError::Invalid(_) => Code::Invalid,
+            Bf2cError::Runtime(_) => Code::Runtime,
+            Bf2cError::Limit(_) => Code::Limit,
+            Bf2cError::Compile(_) => Code::Compile,
+            #[cfg(feature = "std")]
+            Bf2cError::Io { .. } => Code::Io,
+        };
+        Diagnostic::new(code, Severity::Error, &e.to_string())
+    }
+}
+
+/// Diagnostics collected from one or more phases, in the order they were
+/// reported.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Diagnostics::default()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.0.push(diagnostic);
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0.iter().filter(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0.iter().filter(|diagnostic| diagnostic.severity == Severity::Warning)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    /// Orders the diagnostics by where they point in the source, those
+    /// without a place first.
+    pub fn sort(&mut self) {
+        self.0.sort_by_key(Diagnostic::offset);
+    }
+}
+
+impl Deref for Diagnostics {
+    type Target = [Diagnostic];
+
+    fn deref(&self) -> &[Diagnostic] {
+        &self.0
+    }
+}
+
+impl Extend<Diagnostic> for Diagnostics {
+    fn extend<I: IntoIterator<Item = Diagnostic>>(&mut self, iter: I) {
+        self.0.extend(iter);
+    }
+}
+
+impl FromIterator<Diagnostic> for Diagnostics {
+    fn from_iter<I: IntoIterator<Item = Diagnostic>>(iter: I) -> Self {
+        Diagnostics(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+
+    use super::super::bf2c::{parse, ParseOptions};
+    use super::super::check::check;
+    use super::super::error::Bf2cError;
+    use super::{Code, Diagnostic, Diagnostics, Severity};
+
+    #[test]
+    fn from_every_phase() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.push(Diagnostic::from(parse("+\n ]", true).unwrap_err()));
+        diagnostics.extend(check("+[]", &ParseOptions::default()));
+        diagnostics.push(Diagnostic::from(&Bf2cError::Limit("step limit of 3 exceeded".to_string())));
+        let codes: Vec<_> = diagnostics.iter().map(|diagnostic| diagnostic.code.as_str()).collect();
+        assert_eq!(codes, ["unmatched-close", "empty-loop", "limit"]);
+        assert_eq!(diagnostics[0].primary_span.map(|span| (span.start, span.line, span.column)), Some((3, 2, 2)));
+        assert_eq!(diagnostics.errors().count(), 2);
+        assert_eq!(diagnostics.warnings().next().unwrap().severity, Severity::Warning);
+        diagnostics.sort();
+        assert_eq!(diagnostics[0].code, Code::Limit);
+        assert_eq!(diagnostics[0].render("", "a.bf"), "a.bf: error: step limit of 3 exceeded");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serializes_codes_by_name() {
+        let diagnostics = check("+[--]", &ParseOptions::default());
+        let json = serde_json::to_string(&diagnostics).unwrap();
+        assert!(json.starts_with(r#"[{"code":"loop-may-not-terminate","severity":"warning","#));
+        assert_eq!(serde_json::from_str::<Diagnostics>(&json).unwrap(), diagnostics);
+    }
+}
@@ -0,0 +1,249 @@
+//! Brainfuck-isomorphic languages, which only spell the eight instructions
+//! differently. The lexer maps their tokens to `BfSymbol`s and everything
+//! after that is shared.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use super::bf2c::BfSymbol;
+use super::error::Bf2cError;
+
+/// The instructions in the order the mapping file and error messages use.
+const INSTRUCTIONS: [(char, BfSymbol); 8] = [
+    ('>', BfSymbol::Right),
+    ('<', BfSymbol::Left),
+    ('+', BfSymbol::Plus),
+    ('-', BfSymbol::Minus),
+    ('.', BfSymbol::Period),
+    (',', BfSymbol::Comma),
+    ('[', BfSymbol::OpenBracket),
+    (']', BfSymbol::CloseBracket),
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Dialect {
+    #[default]
+    Brainfuck,
+    /// Pairs of `Ook.`, `Ook?` and `Ook!`.
+    Ook,
+    /// Ook! spelled with `Blub` instead of `Ook`.
+    Blub,
+    /// One token per instruction, from a mapping table.
+    Custom(TokenTrie),
+}
+
+impl Dialect {
+    /// Reads a mapping table: one `<instruction> <token>` pair per line, for
+    /// example `+ ▲` or `+ inc`, mapping each of the eight instructions
+    /// exactly once. Tokens are any text without whitespace. Blank lines and
+    /// lines starting with `#` are skipped.
+    pub fn custom(table: &str) -> Result<Dialect, Bf2cError> {
+        let mut tokens = TokenTrie::new();
+        let mut mapped = Vec::new();
+        for (number, line) in table.lines().enumerate().map(|(index, line)| (index + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (Some(instruction), Some(token), None) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(Bf2cError::Invalid(format!("line {}: expected an instruction and its token", number)));
+            };
+            let symbol = INSTRUCTIONS
+                .iter()
+                .find(|&&(c, _)| instruction.chars().eq([c]))
+                .map(|&(_, symbol)| symbol)
+                .ok_or_else(|| Bf2cError::Invalid(format!("line {}: '{}' is not a Brainfuck instruction", number, instruction)))?;
+            if mapped.contains(&symbol) {
+                return Err(Bf2cError::Invalid(format!("line {}: '{}' is mapped twice", number, instruction)));
+            }
+            if !tokens.insert(token, symbol) {
+                return Err(Bf2cError::Invalid(format!("line {}: '{}' already stands for another instruction", number, token)));
+            }
+            mapped.push(symbol);
+        }
+        if let Some((c, _)) = INSTRUCTIONS.iter().find(|(_, symbol)| !mapped.contains(symbol)) {
+            return Err(Bf2cError::Invalid(format!("no token for '{}'", c)));
+        }
+        Ok(Dialect::Custom(tokens))
+    }
+
+    /// Tokens of `buf` with their byte ranges. Anything that is not a token
+    /// is a comment. Not used for plain Brainfuck, which the parser lexes
+    /// itself.
+    pub(crate) fn lex(&self, buf: &str) -> (Vec<BfSymbol>, Vec<Range<usize>>) {
+        match self {
+            Dialect::Brainfuck => unreachable!("Brainfuck is lexed by the parser"),
+            Dialect::Ook => lex_pairs(buf, "Ook"),
+            Dialect::Blub => lex_pairs(buf, "Blub"),
+            Dialect::Custom(tokens) => tokens.lex(buf),
+        }
+    }
+}
+
+/// Tokens of a custom dialect, stored as a trie so the lexer can find the
+/// longest token at each position in one pass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenTrie {
+    /// The root is the first node.
+    nodes: Vec<TrieNode>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct TrieNode {
+    children: BTreeMap<char, usize>,
+    /// Instruction of the token ending here, if any.
+    symbol: Option<BfSymbol>,
+}
+
+impl TokenTrie {
+    fn new() -> Self {
+        TokenTrie { nodes: vec![TrieNode::default()] }
+    }
+
+    /// Adds `token`. Returns false if it is already there.
+    fn insert(&mut self, token: &str, symbol: BfSymbol) -> bool {
+        let mut node = 0;
+        for c in token.chars() {
+            node = match self.nodes[node].children.get(&c) {
+                Some(&child) => child,
+                None => {
+                    self.nodes.push(TrieNode::default());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children.insert(c, child);
+                    child
+                }
+            };
+        }
+        self.nodes[node].symbol.replace(symbol).is_none()
+    }
+
+    /// Length in bytes and instruction of the longest token `text` starts with.
+    fn longest_match(&self, text: &str) -> Option<(usize, BfSymbol)> {
+        let mut node = 0;
+        let mut found = None;
+        for (offset, c) in text.char_indices() {
+            let Some(&child) = self.nodes[node].children.get(&c) else {
+                break;
+            };
+            node = child;
+            if let Some(symbol) = self.nodes[node].symbol {
+                found = Some((offset + c.len_utf8(), symbol));
+            }
+        }
+        found
+    }
+
+    fn lex(&self, buf: &str) -> (Vec<BfSymbol>, Vec<Range<usize>>) {
+        let mut symbols = Vec::new();
+        let mut spans = Vec::new();
+        let mut offset = 0;
+        while let Some(c) = buf[offset..].chars().next() {
+            match self.longest_match(&buf[offset..]) {
+                Some((len, symbol)) => {
+                    symbols.push(symbol);
+                    spans.push(offset..offset + len);
+                    offset += len;
+                }
+                None => offset += c.len_utf8(),
+            }
+        }
+        (symbols, spans)
+    }
+}
+
+/// Lexes Ook!-style pairs of `<word>.`, `<word>?` and `<word>!`. Other words
+/// are comments, and so is a final word without a partner.
+fn lex_pairs(buf: &str, word: &str) -> (Vec<BfSymbol>, Vec<Range<usize>>) {
+    let mut symbols = Vec::new();
+    let mut spans = Vec::new();
+    let mut first: Option<(usize, char)> = None;
+    for (offset, text) in words(buf) {
+        let Some(mark) = text.strip_prefix(word).filter(|mark| matches!(*mark, "." | "?" | "!")) else {
+            continue;
+        };
+        let mark = mark.chars().next().expect("mark is one character");
+        let Some((start, first_mark)) = first.take() else {
+            first = Some((offset, mark));
+            continue;
+        };
+        let symbol = match (first_mark, mark) {
+            ('.', '?') => BfSymbol::Right,
+            ('?', '.') => BfSymbol::Left,
+            ('.', '.') => BfSymbol::Plus,
+            ('!', '!') => BfSymbol::Minus,
+            ('!', '.') => BfSymbol::Period,
+            ('.', '!') => BfSymbol::Comma,
+            ('!', '?') => BfSymbol::OpenBracket,
+            ('?', '!') => BfSymbol::CloseBracket,
+            // `Ook? Ook?` has no meaning
+            _ => continue,
+        };
+        symbols.push(symbol);
+        spans.push(start..offset + text.len());
+    }
+    (symbols, spans)
+}
+
+/// Whitespace separated words of `buf` with their byte offsets.
+fn words(buf: &str) -> impl Iterator<Item = (usize, &str)> {
+    buf.split(char::is_whitespace)
+        .filter(|word| !word.is_empty())
+        .map(move |word| (word.as_ptr() as usize - buf.as_ptr() as usize, word))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::super::bf2c::{parse_with_options, parse_with_spans, BfSymbol, ParseOptions};
+    use super::Dialect;
+
+    fn tokens(dialect: Dialect, source: &str) -> Vec<BfSymbol> {
+        let options = ParseOptions { dialect, ..ParseOptions::default() };
+        parse_with_options(source, true, &options).unwrap().0
+    }
+
+    #[test]
+    fn ook_pairs() {
+        let bf = tokens(Dialect::Brainfuck, "><+-.,[]");
+        let ook = "Ook. Ook? Ook? Ook. Ook. Ook. Ook! Ook!\nOok! Ook. Ook. Ook! Ook! Ook? Ook? Ook!";
+        assert_eq!(tokens(Dialect::Ook, ook), bf);
+        assert_eq!(tokens(Dialect::Blub, &ook.replace("Ook", "Blub")), bf);
+        // prose and a dangling word are comments
+        assert_eq!(tokens(Dialect::Ook, "Say Ook. then Ook. Ook!"), vec![BfSymbol::Plus]);
+    }
+
+    #[test]
+    fn ook_offsets_point_at_the_first_word() {
+        let options = ParseOptions { dialect: Dialect::Ook, ..ParseOptions::default() };
+        let (_, offsets) = parse_with_options("Ook. Ook.\n  Ook! Ook?", false, &options).unwrap();
+        assert_eq!(offsets, vec![0, 12]);
+        let e = parse_with_options("Ook. Ook.\n  Ook! Ook?", true, &options).unwrap_err();
+        assert_eq!(e.to_string(), "'[' opened at line 2, column 3 is never closed");
+    }
+
+    #[test]
+    fn custom_table() {
+        let dialect = Dialect::custom("# arrows\n> →\n< ←\n+ ▲\n- ▼\n. o\n, i\n[ (\n] )\n").unwrap();
+        assert_eq!(tokens(dialect, "▲(▼→▲←) o"), tokens(Dialect::Brainfuck, "+[->+<]."));
+        assert_eq!(Dialect::custom("> a\n< a").unwrap_err().to_string(), "line 2: 'a' already stands for another instruction");
+        assert_eq!(Dialect::custom("> a\n> b").unwrap_err().to_string(), "line 2: '>' is mapped twice");
+        assert_eq!(Dialect::custom("> a").unwrap_err().to_string(), "no token for '<'");
+    }
+
+    #[test]
+    fn longest_token_wins() {
+        let table = "> right\n< left\n+ inc\n- incr\n. out\n, in\n[ loop\n] end\n";
+        let dialect = Dialect::custom(table).unwrap();
+        // `incr` is not `inc` followed by a comment, and prose in between is ignored
+        assert_eq!(tokens(dialect.clone(), "inc incr in, loop incr end out"), tokens(Dialect::Brainfuck, "+-,[-]."));
+        let options = ParseOptions { dialect, ..ParseOptions::default() };
+        let (_, spans) = parse_with_spans("é incr", false, &options).unwrap();
+        assert_eq!(spans, vec![3..7]);
+    }
+}
@@ -0,0 +1,181 @@
+//! Graphviz rendering of the optimized IR, to see how loops nest and how the
+//! optimizer classified them.
+//!
+//! General loops are drawn as clusters around their body, with the loop test
+//! as a diamond. Zero, scan and multiplication loops are single nodes, since
+//! they no longer branch, and runs of other statements are merged into one
+//! box. Edges follow the control flow: dashed edges go back to a loop test.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::slice;
+
+use super::bf2c::Span;
+use super::localop::{Prog, Stmt};
+
+/// Statements listed in the box of a straight-line run before the rest is
+/// summarized.
+const RUN_LINES: usize = 8;
+
+/// `prog` as a Graphviz `digraph`. `spans` are the statement spans from
+/// `optimize_with_spans`, used to label loops with where they are in the
+/// source.
+pub fn to_dot(prog: &Prog, spans: Option<&[Span]>) -> String {
+    let mut graph = Graph { out: String::new(), nodes: 0, spans: spans.map(|spans| spans.iter()) };
+    graph.out += "digraph ir {\n    node [fontname=\"monospace\", shape=box];\n";
+    let start = graph.node(1, "start", "shape=circle");
+    let (last, label) = graph.block(prog, start, "", 1);
+    let end = graph.node(1, "end", "shape=doublecircle");
+    graph.edge(1, last, end, label, "");
+    graph.out += "}\n";
+    graph.out
+}
+
+struct Graph<'a> {
+    out: String,
+    nodes: usize,
+    spans: Option<slice::Iter<'a, Span>>,
+}
+
+impl Graph<'_> {
+    /// Adds the statements of `prog` after node `prev`, the first edge
+    /// labelled `label`. Returns the last node and the label of the edge
+    /// leaving it.
+    fn block(&mut self, prog: &Prog, mut prev: usize, mut label: &'static str, depth: usize) -> (usize, &'static str) {
+        let mut run = Vec::new();
+        for stmt in prog {
+            let span = self.spans.as_mut().and_then(Iterator::next).copied();
+            let (kind, color) = match stmt {
+                Stmt::Loop(body) => {
+                    (prev, label) = self.flush(&mut run, prev, label, depth);
+                    let id = self.nodes;
+                    let indent = "    ".repeat(depth);
+                    writeln!(self.out, "{}subgraph cluster_{} {{", indent, id).unwrap();
+                    writeln!(self.out, "{}    label=\"{}\";\n{}    style=rounded;", indent, located("loop", span), indent).unwrap();
+                    let test = self.node(depth + 1, "*ptr != 0", "shape=diamond");
+                    self.edge(depth + 1, prev, test, label, "");
+                    let (last, exit) = self.block(body, test, "nonzero", depth + 1);
+                    self.edge(depth + 1, last, test, exit, "style=dashed");
+                    writeln!(self.out, "{}}}", indent).unwrap();
+                    (prev, label) = (test, "zero");
+                    continue;
+                }
+                Stmt::ZeroLoop => ("zero loop".into(), "lightblue"),
+                Stmt::ScanLoop(direction) => (format!("scan loop, step {:+}", direction), "lightyellow"),
+                Stmt::MultiplicationLoop(decrement, effects) => {
+                    let mut kind = format!("multiplication loop, {:+} per iteration", -decrement);
+                    for (offset, factor) in effects {
+                        write!(kind, "\\ncell {:+}: {:+} per iteration", offset, factor).unwrap();
+                    }
+                    (kind, "palegreen")
+                }
+                _ => {
+                    run.push(stmt);
+                    continue;
+                }
+            };
+            (prev, label) = self.flush(&mut run, prev, label, depth);
+            let node = self.node(depth, &located(&kind, span), &format!("style=filled, fillcolor={}", color));
+            self.edge(depth, prev, node, label, "");
+            (prev, label) = (node, "");
+        }
+        self.flush(&mut run, prev, label, depth)
+    }
+
+    /// Adds a box for the straight-line statements in `run`, if any.
+    fn flush(&mut self, run: &mut Vec<&Stmt>, prev: usize, label: &'static str, depth: usize) -> (usize, &'static str) {
+        if run.is_empty() {
+            return (prev, label);
+        }
+        let mut text = String::new();
+        for stmt in run.iter().take(RUN_LINES) {
+            write!(text, "{:?}\\l", stmt).unwrap();
+        }
+        if run.len() > RUN_LINES {
+            write!(text, "({} more)\\l", run.len() - RUN_LINES).unwrap();
+        }
+        run.clear();
+        let node = self.node(depth, &text, "");
+        self.edge(depth, prev, node, label, "");
+        (node, "")
+    }
+
+    fn node(&mut self, depth: usize, label: &str, attributes: &str) -> usize {
+        let id = self.nodes;
+        self.nodes += 1;
+        let separator = if attributes.is_empty() { "" } else { ", " };
+        writeln!(self.out, "{}n{} [label=\"{}\"{}{}];", "    ".repeat(depth), id, label, separator, attributes).unwrap();
+        id
+    }
+
+    fn edge(&mut self, depth: usize, from: usize, to: usize, label: &str, attributes: &str) {
+        let mut list = Vec::new();
+        if !label.is_empty() {
+            list.push(format!("label=\"{}\"", label));
+        }
+        if !attributes.is_empty() {
+            list.push(attributes.into());
+        }
+        let attributes = if list.is_empty() { String::new() } else { format!(" [{}]", list.join(", ")) };
+        writeln!(self.out, "{}n{} -> n{}{};", "    ".repeat(depth), from, to, attributes).unwrap();
+    }
+}
+
+/// `what`, followed by the line and column of `span` if known.
+fn located(what: &str, span: Option<Span>) -> String {
+    match span {
+        Some(span) => format!("{}\\nline {}, column {}", what, span.line, span.column),
+        None => what.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::super::bf2c::{parse_spanned, parse_without_verification, ParseOptions};
+    use super::super::localop::{optimize, optimize_with_spans};
+    use super::to_dot;
+
+    #[test]
+    fn draws_loops_as_clusters() {
+        let dot = to_dot(&optimize(&parse_without_verification("+[>[-]<-.]")), None);
+        let expected = indoc! {r#"
+            digraph ir {
+                node [fontname="monospace", shape=box];
+                n0 [label="start", shape=circle];
+                n1 [label="Add(1)\l"];
+                n0 -> n1;
+                subgraph cluster_2 {
+                    label="loop";
+                    style=rounded;
+                    n2 [label="*ptr != 0", shape=diamond];
+                    n1 -> n2;
+                    n3 [label="Move(1)\l"];
+                    n2 -> n3 [label="nonzero"];
+                    n4 [label="zero loop", style=filled, fillcolor=lightblue];
+                    n3 -> n4;
+                    n5 [label="Move(-1)\lAdd(-1)\lOutput(1)\l"];
+                    n4 -> n5;
+                    n5 -> n2 [style=dashed];
+                }
+                n6 [label="end", shape=doublecircle];
+                n2 -> n6 [label="zero"];
+            }
+        "#};
+        assert_eq!(dot, expected);
+    }
+
+    #[test]
+    fn labels_loops_with_their_place() {
+        let tokens = parse_spanned("+\n [->++<]\n[[]>]", &ParseOptions::default()).unwrap();
+        let (prog, spans) = optimize_with_spans(&tokens);
+        let dot = to_dot(&prog, Some(&spans));
+        assert!(dot.contains(r#"label="multiplication loop, -1 per iteration\ncell +1: +2 per iteration\nline 2, column 2""#));
+        assert!(dot.contains(r#"label="loop\nline 3, column 1";"#));
+        // the empty inner loop goes straight back to its test
+        assert!(dot.contains(r#"n4 -> n4 [label="nonzero", style=dashed];"#));
+    }
+}
@@ -0,0 +1,101 @@
+//! The error type returned by the library.
+
+use alloc::string::{String, ToString};
+use core::error::Error;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
+use super::bf2c::ParseError;
+
+#[derive(Debug)]
+pub enum Bf2cError {
+    /// The Brainfuck source has an unmatched bracket.
+    Parse(ParseError),
+    /// Some other input is malformed: a token stream, mapping table, trace or
+    /// snapshot.
+    Invalid(String),
+    /// The program failed while running, for example by moving the data
+    /// pointer off the tape.
+    Runtime(String),
+    /// A step or time limit was exceeded.
+    Limit(String),
+    /// The generated C could not be compiled, or the result could not be run.
+    Compile(String),
+    /// Reading or writing failed. `action` says what was being done, such as
+    /// `read input`.
+    #[cfg(feature = "std")]
+    Io { action: String, source: io::Error },
+}
+
+#[cfg(feature = "std")]
+impl Bf2cError {
+    pub fn io(action: &str, source: io::Error) -> Self {
+        Bf2cError::Io { action: action.to_string(), source }
+    }
+}
+
+impl fmt::Display for Bf2cError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bf2cError::Parse(e) => e.fmt(f),
+            Bf2cError::Invalid(message)
+            | Bf2cError::Runtime(message)
+            | Bf2cError::Limit(message)
+            | Bf2cError::Compile(message) => f.write_str(message),
+            #[cfg(feature = "std")]
+            Bf2cError::Io { action, source } => write!(f, "failed to {}: {}", action, source),
+        }
+    }
+}
+
+impl Error for Bf2cError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Bf2cError::Parse(e) => Some(e),
+            #[cfg(feature = "std")]
+            Bf2cError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseError> for Bf2cError {
+    fn from(e: ParseError) -> Self {
+        Bf2cError::Parse(e)
+    }
+}
+
+/// For the debugger and REPL, which report errors as text.
+impl From<Bf2cError> for String {
+    fn from(e: Bf2cError) -> String {
+        e.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use core::error::Error;
+    #[cfg(feature = "std")]
+    use std::io;
+
+    use super::super::bf2c::parse;
+    use super::Bf2cError;
+
+    #[test]
+    fn display_and_source() {
+        let e = Bf2cError::from(parse("+]", true).unwrap_err());
+        assert_eq!(e.to_string(), "missing open bracket for ']' at line 1, column 2");
+        assert!(e.source().is_some());
+        assert!(Bf2cError::Limit("step limit of 3 exceeded at offset 0".to_string()).source().is_none());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn io_errors_say_what_failed() {
+        let e = Bf2cError::io("read input", io::Error::from(io::ErrorKind::UnexpectedEof));
+        assert_eq!(e.to_string(), "failed to read input: unexpected end of file");
+        assert!(e.source().is_some());
+    }
+}
@@ -0,0 +1,148 @@
+//! C interface to the transpiler, for calling it in-process from C and C++.
+//! Build the shared library with
+//! `cargo rustc --lib --release --features ffi --crate-type cdylib`.
+//!
+//! ```c
+//! typedef struct {
+//!     size_t tape_size;   /* 0 for the default */
+//!     uint8_t cell_bits;  /* 8, 16 or 32; 0 for 8 */
+//!     uint8_t opt_level;
+//!     bool debug;         /* keep `#` as a tape dump */
+//! } bf2c_options;
+//!
+//! int bf2c_transpile(const char *source, const bf2c_options *options, char **out, char **err);
+//! void bf2c_free(char *s);
+//! ```
+//!
+//! Strings returned through `out` and `err` belong to the caller and must be
+//! released with `bf2c_free`, never with `free`.
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use super::bf2c::ParseOptions;
+use super::error::Bf2cError;
+use super::transpiler::{CellWidth, Transpiler};
+
+/// Configuration of `bf2c_transpile`. Zeroed fields select the defaults.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct bf2c_options {
+    pub tape_size: usize,
+    pub cell_bits: u8,
+    pub opt_level: u8,
+    pub debug: bool,
+}
+
+impl bf2c_options {
+    fn transpiler(&self) -> Result<Transpiler, Bf2cError> {
+        let cell_width = match self.cell_bits {
+            0 | 8 => CellWidth::U8,
+            16 => CellWidth::U16,
+            32 => CellWidth::U32,
+            bits => return Err(Bf2cError::Invalid(format!("unsupported cell width of {} bits", bits))),
+        };
+        let mut builder = Transpiler::builder()
+            .options(ParseOptions { debug: self.debug, ..ParseOptions::default() })
+            .cell_width(cell_width)
+            .opt_level(self.opt_level);
+        if self.tape_size != 0 {
+            builder = builder.tape_size(self.tape_size);
+        }
+        builder.build()
+    }
+}
+
+/// Transpiles the NUL-terminated `source` to C. On success stores the code in
+/// `*out` and returns 0, otherwise stores a message in `*err` and returns -1.
+/// `options` may be null for the defaults, and `err` may be null if the
+/// message is not wanted.
+///
+/// # Safety
+///
+/// `source` must point to a NUL-terminated string, `options` must be null or
+/// valid, and `out` and `err` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn bf2c_transpile(source: *const c_char, options: *const bf2c_options, out: *mut *mut c_char, err: *mut *mut c_char) -> c_int {
+    if !out.is_null() {
+        *out = ptr::null_mut();
+    }
+    if !err.is_null() {
+        *err = ptr::null_mut();
+    }
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        if source.is_null() || out.is_null() {
+            return Err("source and out must not be null".to_string());
+        }
+        let source = CStr::from_ptr(source).to_str().map_err(|e| format!("source is not UTF-8: {}", e))?;
+        let options = options.as_ref().copied().unwrap_or_default();
+        let code = options.transpiler()?.transpile(source)?;
+        // the generated code has no NUL bytes, they are comments in Brainfuck
+        Ok(CString::new(code).expect("NUL in generated code"))
+    }));
+    match result {
+        Ok(Ok(code)) => {
+            *out = code.into_raw();
+            0
+        }
+        Ok(Err(message)) => {
+            set_error(err, message);
+            -1
+        }
+        Err(_) => {
+            set_error(err, "internal error in the transpiler".to_string());
+            -1
+        }
+    }
+}
+
+/// Releases a string returned by this library. Null is ignored.
+///
+/// # Safety
+///
+/// `s` must be null or a string from `bf2c_transpile` that was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn bf2c_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+unsafe fn set_error(err: *mut *mut c_char, message: String) {
+    if !err.is_null() {
+        *err = CString::new(message.replace('\0', " ")).unwrap().into_raw();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bf2c_free, bf2c_options, bf2c_transpile};
+    use std::ffi::{CStr, CString};
+    use std::ptr;
+
+    /// Result code and the string stored in `out` or `err`.
+    fn transpile(source: &str, options: Option<bf2c_options>) -> (i32, String) {
+        let source = CString::new(source).unwrap();
+        let options = options.as_ref().map_or(ptr::null(), |options| options as *const _);
+        let (mut out, mut err) = (ptr::null_mut(), ptr::null_mut());
+        unsafe {
+            let status = bf2c_transpile(source.as_ptr(), options, &mut out, &mut err);
+            let s = if status == 0 { out } else { err };
+            let text = CStr::from_ptr(s).to_str().unwrap().to_string();
+            bf2c_free(out);
+            bf2c_free(err);
+            (status, text)
+        }
+    }
+
+    #[test]
+    fn transpiles_and_reports_errors() {
+        let (status, code) = transpile("+.", Some(bf2c_options { cell_bits: 16, tape_size: 100, ..bf2c_options::default() }));
+        assert_eq!(status, 0);
+        assert!(code.contains("uint16_t tape[100];"));
+        assert_eq!(transpile("+]", None), (-1, "missing open bracket for ']' at line 1, column 2".to_string()));
+        assert_eq!(transpile("+", Some(bf2c_options { cell_bits: 12, ..bf2c_options::default() })).0, -1);
+    }
+}
@@ -0,0 +1,168 @@
+//! Tape activity recorded by the interpreter's `Machine`, rendered as an
+//! ASCII heatmap or an HTML report.
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use super::interp::Tape;
+
+/// Cells per row of a rendered heatmap.
+const ROW_LEN: usize = 32;
+
+/// Shades for written cells, from least to most written. Cells the pointer
+/// stopped on without writing them are shown as `.`.
+const SHADES: &[u8] = b":-=+*#%@";
+
+/// How often every cell was written and visited.
+#[derive(Debug, Clone)]
+pub struct Activity {
+    pub writes: Vec<u64>,
+    /// Number of steps that left the pointer on each cell.
+    pub visits: Vec<u64>,
+}
+
+impl Activity {
+    pub fn new(cells: usize) -> Self {
+        Activity { writes: vec![0; cells], visits: vec![0; cells] }
+    }
+
+    pub(crate) fn record(&mut self, written: &[usize], ptr: usize) {
+        for &cell in written {
+            self.writes[cell] += 1;
+        }
+        self.visits[ptr] += 1;
+    }
+
+    /// One past the highest cell that was written or visited.
+    pub fn extent(&self) -> usize {
+        let touched = |counts: &[u64]| counts.iter().rposition(|&n| n > 0).map_or(0, |last| last + 1);
+        touched(&self.writes).max(touched(&self.visits))
+    }
+
+    /// Number of cells that were written or visited.
+    pub fn touched(&self) -> usize {
+        (0..self.extent()).filter(|&cell| self.writes[cell] > 0 || self.visits[cell] > 0).count()
+    }
+
+    /// Shade index of `cell`, `None` for cells that were never written.
+    fn level(&self, cell: usize, max: u64) -> Option<usize> {
+        let writes = self.writes[cell];
+        if writes == 0 {
+            return None;
+        }
+        // logarithmic, so a few hot counters do not wash out everything else
+        let scale = (writes as f64).ln_1p() / (max as f64).ln_1p();
+        Some(((SHADES.len() - 1) as f64 * scale).round() as usize)
+    }
+
+    /// Renders the touched part of the tape, `ROW_LEN` cells per row.
+    pub fn ascii(&self) -> String {
+        let extent = self.extent();
+        let max = self.writes.iter().copied().max().unwrap_or(0);
+        let mut out = String::new();
+        writeln!(out, "{} cells touched, cells 0..{}", self.touched(), extent).unwrap();
+        for row in (0..extent).step_by(ROW_LEN) {
+            let line: String = (row..(row + ROW_LEN).min(extent))
+                .map(|cell| match self.level(cell, max) {
+                    Some(level) => SHADES[level] as char,
+                    None if self.visits[cell] > 0 => '.',
+                    None => ' ',
+                })
+                .collect();
+            writeln!(out, "{:>6} |{}|", row, line).unwrap();
+        }
+        writeln!(out, "writes: {} (few to many), '.' visited only", String::from_utf8_lossy(SHADES)).unwrap();
+        out
+    }
+
+    /// Standalone HTML page with one colored square per touched cell,
+    /// annotated with its counts and its value on `tape`.
+    pub fn html(&self, tape: &Tape) -> String {
+        let extent = self.extent();
+        let max = self.writes.iter().copied().max().unwrap_or(0);
+        let mut out = String::new();
+        out += "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Tape activity</title>\n<style>\n";
+        out += "body { font-family: monospace; }\n";
+        out += "td { width: 1.6em; height: 1.6em; text-align: center; font-size: 70%; border: 1px solid #ddd; }\n";
+        out += "th { text-align: right; padding-right: 0.5em; font-weight: normal; }\n";
+        out += "</style>\n</head>\n<body>\n";
+        writeln!(out, "<p>{} cells touched, cells 0..{}, at most {} writes per cell</p>", self.touched(), extent, max)
+            .unwrap();
+        out += "<table>\n";
+        for row in (0..extent).step_by(ROW_LEN) {
+            write!(out, "<tr><th>{}</th>", row).unwrap();
+            for cell in row..(row + ROW_LEN).min(extent) {
+                let color = match self.level(cell, max) {
+                    // from pale yellow to dark red
+                    Some(level) => {
+                        let heat = (level + 1) as f64 / SHADES.len() as f64;
+                        format!("hsl({:.0}, 100%, {:.0}%)", 60.0 - 60.0 * heat, 90.0 - 55.0 * heat)
+                    }
+                    None if self.visits[cell] > 0 => "#e8f0ff".to_string(),
+                    None => "#fff".to_string(),
+                };
+                let border = if cell == tape.ptr { " outline: 2px solid #000;" } else { "" };
+                write!(
+                    out,
+                    "<td style=\"background: {};{}\" title=\"cell {}: {} writes, {} visits, value {}\">{}</td>",
+                    color, border, cell, self.writes[cell], self.visits[cell], tape.cells[cell], tape.cells[cell]
+                )
+                .unwrap();
+            }
+            out += "</tr>\n";
+        }
+        out += "</table>\n</body>\n</html>\n";
+        out
+    }
+
+    /// HTML for `.html`/`.htm` files, the ASCII heatmap otherwise.
+    pub fn render_for(&self, path: &Path, tape: &Tape) -> String {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("html" | "htm") => self.html(tape),
+            _ => self.ascii(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bf2c::ParseOptions;
+    use super::super::interp::{Limits, Machine};
+    use super::Activity;
+
+    #[test]
+    fn records_writes_and_visits() {
+        let mut machine = Machine::from_source("+++[->>++<<]>", true, &ParseOptions::default()).unwrap();
+        machine.set_activity();
+        machine.run(&mut &b""[..], &mut Vec::new(), &Limits::default()).unwrap();
+        let activity = machine.activity().unwrap();
+        assert_eq!(&activity.writes[..3], &[2, 0, 1]);
+        assert_eq!(&activity.visits[..3], &[2, 1, 0]);
+        assert_eq!(activity.extent(), 3);
+        assert_eq!(activity.touched(), 3);
+    }
+
+    #[test]
+    fn ascii_shades() {
+        let mut activity = Activity::new(40);
+        activity.writes[0] = 100;
+        activity.writes[1] = 1;
+        activity.visits[3] = 1;
+        activity.writes[33] = 10;
+        let out = activity.ascii();
+        assert_eq!(
+            out.lines().collect::<Vec<_>>()[..3],
+            ["4 cells touched, cells 0..34", "     0 |@- .                            |", "    32 | *|"]
+        );
+    }
+
+    #[test]
+    fn html_marks_the_pointer() {
+        let mut machine = Machine::from_source("+>++", false, &ParseOptions::default()).unwrap();
+        machine.set_activity();
+        machine.run(&mut &b""[..], &mut Vec::new(), &Limits::default()).unwrap();
+        let html = machine.activity().unwrap().html(&machine.tape);
+        assert!(html.contains("title=\"cell 0: 1 writes, 1 visits, value 1\""));
+        assert!(html.contains("outline: 2px solid #000;\" title=\"cell 1: 2 writes, 3 visits, value 2\""));
+    }
+}
@@ -0,0 +1,200 @@
+//! `@include "file.bf"` directives, expanded before parsing.
+//!
+//! A directive takes a whole line and is replaced by the contents of the
+//! file, resolved relative to the including file. The expanded text keeps a
+//! map back to the files it came from, so errors can point into the right
+//! file.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Program text assembled from a file and everything it includes.
+#[derive(Debug, Clone)]
+pub struct Expanded {
+    pub text: String,
+    /// Path and contents of every file, the including one first.
+    files: Vec<(PathBuf, String)>,
+    /// Consecutive pieces of `text`, sorted by start.
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Piece {
+    /// Start in the expanded text.
+    start: usize,
+    file: usize,
+    /// Start in the file the piece was copied from.
+    file_start: usize,
+}
+
+#[derive(Debug)]
+pub enum IncludeError {
+    /// An included file could not be read.
+    Read(String),
+    /// A malformed directive or an include cycle.
+    Invalid(String),
+}
+
+impl fmt::Display for IncludeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IncludeError::Read(message) | IncludeError::Invalid(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Expanded {
+    /// `source`, read from `path`, taken as is.
+    pub fn single(path: &Path, source: String) -> Self {
+        Expanded {
+            text: source.clone(),
+            files: vec![(path.to_path_buf(), source)],
+            pieces: vec![Piece { start: 0, file: 0, file_start: 0 }],
+        }
+    }
+
+    /// Expands the directives in `source`, read from `path`, and in the
+    /// files it includes. Paths are relative to the including file, or to
+    /// the working directory for `-`.
+    pub fn new(path: &Path, source: String) -> Result<Self, IncludeError> {
+        let mut expanded = Expanded { text: String::new(), files: vec![(path.to_path_buf(), source)], pieces: Vec::new() };
+        let root = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        expanded.expand(0, &mut vec![root])?;
+        Ok(expanded)
+    }
+
+    /// `programs` one after the other, separated by `separator`.
+    pub fn concat(programs: Vec<Expanded>, separator: &str) -> Self {
+        let mut joined = Expanded { text: String::new(), files: Vec::new(), pieces: Vec::new() };
+        for (index, program) in programs.into_iter().enumerate() {
+            if index > 0 {
+                joined.text += separator;
+            }
+            let (start, first_file) = (joined.text.len(), joined.files.len());
+            joined.pieces.extend(program.pieces.iter().map(|piece| Piece {
+                start: start + piece.start,
+                file: first_file + piece.file,
+                file_start: piece.file_start,
+            }));
+            joined.text += &program.text;
+            joined.files.extend(program.files);
+        }
+        joined
+    }
+
+    /// Path and contents of the file `offset` in the expanded text comes
+    /// from, and the offset in that file.
+    pub fn locate(&self, offset: usize) -> (&Path, &str, usize) {
+        let index = self.pieces.partition_point(|piece| piece.start <= offset).saturating_sub(1);
+        let piece = self.pieces[index];
+        let (path, source) = &self.files[piece.file];
+        (path, source, piece.file_start + offset - piece.start)
+    }
+
+    /// Appends file `file` to the text. `stack` holds the canonical paths
+    /// of the files being expanded, innermost last.
+    fn expand(&mut self, file: usize, stack: &mut Vec<PathBuf>) -> Result<(), IncludeError> {
+        let source = self.files[file].1.clone();
+        let mut copied = 0;
+        let mut line_start = 0;
+        for (number, line) in source.split_inclusive('\n').enumerate() {
+            let offset = line_start;
+            line_start += line.len();
+            let Some(argument) = line.trim_start().strip_prefix("@include") else {
+                continue;
+            };
+            let at = format!("{}:{}", self.files[file].0.display(), number + 1);
+            let name = argument
+                .trim()
+                .strip_prefix('"')
+                .and_then(|rest| rest.strip_suffix('"'))
+                .filter(|name| !name.is_empty() && !name.contains('"'))
+                .ok_or_else(|| IncludeError::Invalid(format!("{}: expected @include \"file\"", at)))?;
+            self.copy(file, copied..offset, &source);
+            // keep the line break so the following lines stay on their own
+            copied = offset + line.trim_end_matches(['\r', '\n']).len();
+
+            let path = match self.files[file].0.parent() {
+                Some(dir) if self.files[file].0 != Path::new("-") => dir.join(name),
+                _ => PathBuf::from(name),
+            };
+            let included = fs::read_to_string(&path)
+                .map_err(|e| IncludeError::Read(format!("{}: cannot include '{}': {}", at, path.display(), e)))?;
+            let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            if let Some(first) = stack.iter().position(|open| *open == canonical) {
+                let chain: Vec<String> =
+                    stack[first..].iter().chain([&canonical]).map(|path| path.display().to_string()).collect();
+                return Err(IncludeError::Invalid(format!("{}: include cycle: {}", at, chain.join(" -> "))));
+            }
+            self.files.push((path, included));
+            stack.push(canonical);
+            self.expand(self.files.len() - 1, stack)?;
+            stack.pop();
+        }
+        self.copy(file, copied..source.len(), &source);
+        Ok(())
+    }
+
+    fn copy(&mut self, file: usize, range: std::ops::Range<usize>, source: &str) {
+        self.pieces.push(Piece { start: self.text.len(), file, file_start: range.start });
+        self.text += &source[range];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expanded, IncludeError};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bf-include-{}-{}", name, std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn expands_relative_to_the_including_file() {
+        let dir = temp_dir("nested");
+        fs::write(dir.join("lib/inner.bf"), "-\n").unwrap();
+        fs::write(dir.join("lib/outer.bf"), "[\n  @include \"inner.bf\"\n]").unwrap();
+        let main = dir.join("main.bf");
+        let expanded = Expanded::new(&main, "+\n@include \"lib/outer.bf\"\n.".to_string());
+        fs::remove_dir_all(&dir).unwrap();
+        let expanded = expanded.unwrap();
+        assert_eq!(expanded.text, "+\n[\n-\n\n]\n.");
+        // the `-` comes from inner.bf, the `.` from main.bf
+        let (path, source, offset) = expanded.locate(4);
+        assert_eq!((path.ends_with("lib/inner.bf"), &source[offset..offset + 1]), (true, "-"));
+        let (path, source, offset) = expanded.locate(9);
+        assert_eq!((path, &source[offset..]), (main.as_path(), "."));
+    }
+
+    #[test]
+    fn concat_keeps_locations() {
+        let a = Expanded::single(Path::new("a.bf"), "+".to_string());
+        let b = Expanded::single(Path::new("b.bf"), "-[".to_string());
+        let joined = Expanded::concat(vec![a, b], "\n");
+        assert_eq!(joined.text, "+\n-[");
+        assert_eq!(joined.locate(3), (Path::new("b.bf"), "-[", 1));
+    }
+
+    #[test]
+    fn rejects_cycles_and_bad_directives() {
+        let dir = temp_dir("cycle");
+        fs::write(dir.join("a.bf"), "@include \"b.bf\"\n").unwrap();
+        fs::write(dir.join("b.bf"), "+\n@include \"a.bf\"\n").unwrap();
+        let cycle = Expanded::new(&dir.join("a.bf"), fs::read_to_string(dir.join("a.bf")).unwrap());
+        let missing = Expanded::new(&dir.join("c.bf"), "@include \"nope.bf\"".to_string());
+        fs::remove_dir_all(&dir).unwrap();
+        let Err(IncludeError::Invalid(message)) = cycle else { panic!("cycle not detected") };
+        let chain = message.split_once("b.bf:2: include cycle: ").unwrap().1;
+        assert_eq!(chain.split(" -> ").map(|path| &path[path.len() - 4..]).collect::<Vec<_>>(), ["a.bf", "b.bf", "a.bf"]);
+        assert!(matches!(missing, Err(IncludeError::Read(_))));
+        let Err(IncludeError::Invalid(message)) = Expanded::new(&dir.join("d.bf"), "@include lib.bf".to_string()) else {
+            panic!("malformed directive accepted")
+        };
+        assert!(message.ends_with("d.bf:1: expected @include \"file\""));
+    }
+}
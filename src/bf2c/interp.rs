@@ -0,0 +1,886 @@
+//! Reference interpreter for Brainfuck programs.
+//!
+//! Programs can be executed either straight from the token stream or from the
+//! local optimization IR, which makes the interpreter double as a semantic
+//! check of the optimizer: both paths must agree on every program.
+//!
+//! Cells have 8 bits, so the interpreter is only a reference for C generated
+//! with `CellWidth::U8`, see `verify::verify_backend`.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use super::bf2c::{parse_with_spans, BfSymbol, ParseOptions};
+use super::error::Bf2cError;
+use super::localop::{inverse_mod_256, optimize_with_ranges, Prog, Stmt};
+use super::heatmap::Activity;
+use super::journal::{Entry, Journal};
+use super::snapshot::Snapshot;
+use super::trace::{Event, EventKind, Recording, Tracer};
+use super::watch::{WatchHit, WatchKind};
+
+pub use super::transpiler::TAPE_SIZE;
+
+/// Cells shown on each side of the pointer by `#`.
+const DEBUG_WINDOW: usize = 4;
+
+/// Steps between two checks of the wall clock when a timeout is set.
+const CLOCK_CHECK_INTERVAL: u64 = 4096;
+
+/// Limits that abort a run instead of letting it hang. `None` is unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Clone)]
+pub struct Tape {
+    pub cells: Vec<u8>,
+    pub ptr: usize,
+}
+
+impl Tape {
+    pub fn new() -> Self {
+        Tape { cells: vec![0; TAPE_SIZE], ptr: 0 }
+    }
+
+    fn index(&self, offset: i32) -> Result<usize, Bf2cError> {
+        let target = self.ptr as i64 + offset as i64;
+        if target < 0 || target >= self.cells.len() as i64 {
+            return Err(Bf2cError::Runtime(format!("data pointer moved out of the tape (cell {})", target)));
+        }
+        Ok(target as usize)
+    }
+
+    fn shift(&mut self, distance: i32) -> Result<(), Bf2cError> {
+        self.ptr = self.index(distance)?;
+        Ok(())
+    }
+
+    fn at(&mut self, offset: i32) -> Result<&mut u8, Bf2cError> {
+        let index = self.index(offset)?;
+        Ok(&mut self.cells[index])
+    }
+
+    fn current(&self) -> u8 {
+        self.cells[self.ptr]
+    }
+
+    fn set(&mut self, value: u8) {
+        self.cells[self.ptr] = value;
+    }
+}
+
+impl Default for Tape {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads one byte. End of input stores 255, matching `*ptr = getchar()` in
+/// the generated C code where `EOF` is truncated to a char.
+pub(crate) fn read_byte<R: Read>(input: &mut R) -> Result<u8, Bf2cError> {
+    let mut buf = [0u8; 1];
+    match input.read(&mut buf) {
+        Ok(0) => Ok(255),
+        Ok(_) => Ok(buf[0]),
+        Err(e) => Err(Bf2cError::io("read input", e)),
+    }
+}
+
+pub(crate) fn write_byte<W: Write>(output: &mut W, byte: u8) -> Result<(), Bf2cError> {
+    output.write_all(&[byte]).map_err(|e| Bf2cError::io("write output", e))
+}
+
+/// The line printed for `#`, in the same format as the generated C code.
+fn debug_line(tape: &Tape) -> String {
+    debug_window(&tape.cells, tape.ptr)
+}
+
+/// `debug_line` for the tape `cells` with the pointer at `ptr`.
+pub(crate) fn debug_window(cells: &[u8], ptr: usize) -> String {
+    let start = ptr.saturating_sub(DEBUG_WINDOW);
+    let end = (ptr + DEBUG_WINDOW + 1).min(cells.len());
+    let mut line = format!("ptr={}:", ptr);
+    for (i, cell) in cells[start..end].iter().enumerate() {
+        if start + i == ptr {
+            line += &format!(" [{}]", cell);
+        } else {
+            line += &format!(" {}", cell);
+        }
+    }
+    line
+}
+
+/// Index of the matching bracket for every bracket in `tokens`.
+fn match_brackets(tokens: &[BfSymbol]) -> Result<Vec<usize>, Bf2cError> {
+    let mut jumps = vec![0; tokens.len()];
+    let mut open = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            BfSymbol::OpenBracket => open.push(i),
+            BfSymbol::CloseBracket => {
+                let start = open.pop().ok_or_else(|| Bf2cError::Invalid("missing open bracket".to_string()))?;
+                jumps[start] = i;
+                jumps[i] = start;
+            }
+            _ => {}
+        }
+    }
+    if !open.is_empty() {
+        return Err(Bf2cError::Invalid("Brainfuck code is not well-formed (Brackets do not match)".to_string()));
+    }
+    Ok(jumps)
+}
+
+/// Executes the raw token stream one symbol at a time.
+pub fn run_symbols<R: Read, W: Write>(
+    tokens: &[BfSymbol],
+    tape: &mut Tape,
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), Bf2cError> {
+    let jumps = match_brackets(tokens)?;
+    let mut pc = 0;
+    while pc < tokens.len() {
+        match tokens[pc] {
+            BfSymbol::Left => tape.shift(-1)?,
+            BfSymbol::Right => tape.shift(1)?,
+            BfSymbol::Plus => tape.set(tape.current().wrapping_add(1)),
+            BfSymbol::Minus => tape.set(tape.current().wrapping_sub(1)),
+            BfSymbol::Period => write_byte(output, tape.current())?,
+            BfSymbol::Comma => tape.set(read_byte(input)?),
+            BfSymbol::OpenBracket => {
+                if tape.current() == 0 {
+                    pc = jumps[pc];
+                }
+            }
+            BfSymbol::CloseBracket => {
+                if tape.current() != 0 {
+                    pc = jumps[pc];
+                }
+            }
+            BfSymbol::Debug => eprintln!("{}", debug_line(tape)),
+        }
+        pc += 1;
+    }
+    output.flush().map_err(|e| Bf2cError::io("write output", e))
+}
+
+/// Executes a program lowered by `localop::optimize`.
+pub fn run_prog<R: Read, W: Write>(
+    prog: &Prog,
+    tape: &mut Tape,
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), Bf2cError> {
+    exec_block(prog, tape, input, output)?;
+    output.flush().map_err(|e| Bf2cError::io("write output", e))
+}
+
+fn exec_block<R: Read, W: Write>(
+    prog: &Prog,
+    tape: &mut Tape,
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), Bf2cError> {
+    for stmt in prog {
+        match stmt {
+            Stmt::Loop(body) => {
+                while tape.current() != 0 {
+                    exec_block(body, tape, input, output)?;
+                }
+            }
+            _ => exec_stmt(stmt, tape, input, output)?,
+        }
+    }
+    Ok(())
+}
+
+/// Executes any statement but `Stmt::Loop`, whose body is run by the caller.
+fn exec_stmt<R: Read, W: Write>(
+    stmt: &Stmt,
+    tape: &mut Tape,
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), Bf2cError> {
+    match stmt {
+        Stmt::Add(delta) => tape.set(tape.current().wrapping_add(*delta as u8)),
+        Stmt::Move(distance) => tape.shift(*distance)?,
+        Stmt::Output(count) => {
+            for _ in 0..*count {
+                write_byte(output, tape.current())?;
+            }
+        }
+        Stmt::Input(count) => {
+            for _ in 0..*count {
+                tape.set(read_byte(input)?);
+            }
+        }
+        Stmt::Loop(_) => unreachable!("loop bodies are executed by exec_block"),
+        Stmt::ZeroLoop => tape.set(0),
+        Stmt::ScanLoop(direction) => {
+            while tape.current() != 0 {
+                tape.shift(*direction)?;
+            }
+        }
+        Stmt::MultiplicationLoop(decrement, effects) => {
+            let x = tape.current();
+            if x == 0 {
+                return Ok(());
+            }
+            let iterations = x.wrapping_mul(inverse_mod_256(*decrement as u8));
+            for &(offset, factor) in effects {
+                let cell = tape.at(offset)?;
+                *cell = cell.wrapping_add((factor as u8).wrapping_mul(iterations));
+            }
+            tape.set(0);
+        }
+        Stmt::Debug => eprintln!("{}", debug_line(tape)),
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Symbol(BfSymbol),
+    Stmt(Stmt),
+    /// Start of a loop, skips past the matching `LoopEnd` on a zero cell.
+    LoopStart(usize),
+    /// End of a loop, jumps back past the matching `LoopStart` otherwise.
+    LoopEnd(usize),
+}
+
+/// Interpreter that executes one instruction or IR statement at a time and
+/// knows which part of the source every step comes from.
+pub struct Machine {
+    ops: Vec<Op>,
+    /// Byte range of the source each op was built from.
+    spans: Vec<Range<usize>>,
+    /// Number of times each op has been executed.
+    hits: Vec<u64>,
+    pub pc: usize,
+    pub tape: Tape,
+    pub steps: u64,
+    tracer: Option<Tracer>,
+    journal: Option<Journal>,
+    activity: Option<Activity>,
+    watches: BTreeMap<usize, WatchKind>,
+    watch_hit: Option<WatchHit>,
+}
+
+impl Machine {
+    /// Parses `source` and steps through its IR statements, or through its
+    /// raw instructions unless `statements` is set.
+    pub fn from_source(source: &str, statements: bool, options: &ParseOptions) -> Result<Self, Bf2cError> {
+        let (tokens, spans) = parse_with_spans(source, true, options)?;
+        if statements {
+            let (prog, ranges) = optimize_with_ranges(&tokens);
+            Ok(Machine::from_prog(&prog, &ranges, &spans))
+        } else {
+            Machine::from_symbols(&tokens, &spans)
+        }
+    }
+
+    /// Steps through raw instructions. `spans` are the source ranges of
+    /// `tokens`, as returned by `parse_with_spans`.
+    pub fn from_symbols(tokens: &[BfSymbol], spans: &[Range<usize>]) -> Result<Self, Bf2cError> {
+        let jumps = match_brackets(tokens)?;
+        let ops = tokens
+            .iter()
+            .enumerate()
+            .map(|(i, token)| match token {
+                BfSymbol::OpenBracket => Op::LoopStart(jumps[i]),
+                BfSymbol::CloseBracket => Op::LoopEnd(jumps[i]),
+                _ => Op::Symbol(*token),
+            })
+            .collect();
+        Ok(Machine::new(ops, spans.to_vec()))
+    }
+
+    /// Steps through IR statements. `ranges` are the token ranges returned by
+    /// `optimize_with_ranges` and `spans` the source ranges of the tokens.
+    pub fn from_prog(prog: &Prog, ranges: &[Range<usize>], spans: &[Range<usize>]) -> Self {
+        let mut ops = Vec::new();
+        let mut token_ranges = Vec::new();
+        flatten(prog, ranges, &mut 0, &mut ops, &mut token_ranges);
+        let spans = token_ranges
+            .into_iter()
+            .map(|range| spans[range.start].start..spans[range.end - 1].end)
+            .collect();
+        Machine::new(ops, spans)
+    }
+
+    fn new(ops: Vec<Op>, spans: Vec<Range<usize>>) -> Self {
+        let hits = vec![0; ops.len()];
+        Machine {
+            ops,
+            spans,
+            hits,
+            pc: 0,
+            tape: Tape::new(),
+            steps: 0,
+            tracer: None,
+            journal: None,
+            activity: None,
+            watches: BTreeMap::new(),
+            watch_hit: None,
+        }
+    }
+
+    /// Records every following step to `tracer`.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Remembers the state overwritten by the last `capacity` steps so they
+    /// can be undone with `step_back`.
+    pub fn set_journal(&mut self, capacity: usize) {
+        self.journal = Some(Journal::new(capacity));
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Counts writes and visits of every cell from now on.
+    pub fn set_activity(&mut self) {
+        self.activity = Some(Activity::new(self.tape.cells.len()));
+    }
+
+    pub fn activity(&self) -> Option<&Activity> {
+        self.activity.as_ref()
+    }
+
+    /// Undoes the last journaled step, restoring the cells it wrote, the
+    /// pointer and the position. Input already consumed and output already
+    /// written stay as they are. Returns `false` if there is nothing to undo.
+    pub fn step_back(&mut self) -> bool {
+        let Some(entry) = self.journal.as_mut().and_then(Journal::pop) else {
+            return false;
+        };
+        for &(cell, value) in entry.cells.iter().rev() {
+            self.tape.cells[cell] = value;
+        }
+        self.tape.ptr = entry.ptr;
+        self.pc = entry.pc;
+        self.hits[entry.pc] -= 1;
+        self.steps -= 1;
+        true
+    }
+
+    /// Stops at the next step that accesses `cell` as selected by `kind`,
+    /// replacing any watchpoint already set on it.
+    pub fn set_watch(&mut self, cell: usize, kind: WatchKind) {
+        self.watches.insert(cell, kind);
+    }
+
+    /// Returns `false` if `cell` was not watched.
+    pub fn remove_watch(&mut self, cell: usize) -> bool {
+        self.watches.remove(&cell).is_some()
+    }
+
+    pub fn watches(&self) -> &BTreeMap<usize, WatchKind> {
+        &self.watches
+    }
+
+    /// The watched cell accessed by the last step, if any. Cleared by the
+    /// next step.
+    pub fn watch_hit(&self) -> Option<WatchHit> {
+        self.watch_hit
+    }
+
+    /// Cells the step at `pc` may write, given the current pointer.
+    fn written_cells(&self) -> Vec<usize> {
+        let ptr = self.tape.ptr;
+        match &self.ops[self.pc] {
+            Op::Symbol(BfSymbol::Plus | BfSymbol::Minus | BfSymbol::Comma) => vec![ptr],
+            Op::Stmt(Stmt::Add(_) | Stmt::Input(_) | Stmt::ZeroLoop) => vec![ptr],
+            Op::Stmt(Stmt::MultiplicationLoop(_, _)) if self.tape.current() == 0 => Vec::new(),
+            Op::Stmt(Stmt::MultiplicationLoop(_, effects)) => std::iter::once(ptr)
+                .chain(effects.iter().filter_map(|&(offset, _)| self.tape.index(offset).ok()))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Cells whose value the step at `pc` depends on, given the current tape.
+    fn read_cells(&self) -> Vec<usize> {
+        let ptr = self.tape.ptr;
+        match &self.ops[self.pc] {
+            Op::Symbol(BfSymbol::Plus | BfSymbol::Minus | BfSymbol::Period) => vec![ptr],
+            Op::LoopStart(_) | Op::LoopEnd(_) => vec![ptr],
+            Op::Stmt(Stmt::Add(_) | Stmt::Output(_)) => vec![ptr],
+            Op::Stmt(Stmt::MultiplicationLoop(_, _)) if self.tape.current() == 0 => vec![ptr],
+            Op::Stmt(Stmt::MultiplicationLoop(_, _)) => self.written_cells(),
+            Op::Stmt(Stmt::ScanLoop(direction)) => {
+                let mut cells = vec![ptr];
+                let mut cell = ptr;
+                while self.tape.cells[cell] != 0 {
+                    match cell.checked_add_signed(*direction as isize).filter(|&next| next < self.tape.cells.len()) {
+                        Some(next) => cell = next,
+                        None => break,
+                    }
+                    cells.push(cell);
+                }
+                cells
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// First watched cell the step at `pc` is about to access, with its
+    /// current value and whether it is written.
+    fn watched_access(&self, written: &[usize]) -> Option<(usize, bool, u8)> {
+        let write = written.iter().find(|cell| self.watches.get(cell).is_some_and(|kind| kind.on_write()));
+        if let Some(&cell) = write {
+            return Some((cell, true, self.tape.cells[cell]));
+        }
+        let read = self.read_cells().into_iter().find(|cell| self.watches.get(cell).is_some_and(|kind| kind.on_read()));
+        read.map(|cell| (cell, false, self.tape.cells[cell]))
+    }
+
+    /// Source range of the step at `pc`.
+    pub fn span_at(&self, pc: usize) -> Range<usize> {
+        self.spans[pc].clone()
+    }
+
+    /// Number of times each step has been executed so far, indexed by `pc`.
+    pub fn hits(&self) -> &[u64] {
+        &self.hits
+    }
+
+    /// `(start, end)` step indices of every loop, in source order. The
+    /// number of completed iterations of a loop is the hit count of its end.
+    pub fn loops(&self) -> Vec<(usize, usize)> {
+        self.ops
+            .iter()
+            .enumerate()
+            .filter_map(|(pc, op)| match op {
+                Op::LoopStart(end) => Some((pc, *end)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether the step at `pc` opens or closes a loop.
+    pub fn is_loop_boundary(&self, pc: usize) -> bool {
+        matches!(self.ops[pc], Op::LoopStart(_) | Op::LoopEnd(_))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.pc >= self.ops.len()
+    }
+
+    /// Source range of the next step, if the program has not finished.
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.spans.get(self.pc).cloned()
+    }
+
+    /// Captures the tape and the position of the next step.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot { tape: self.tape.clone(), offset: self.span().map(|span| span.start) }
+    }
+
+    /// Continues from `snapshot`: its tape replaces the current one, and
+    /// execution moves to the step at its offset, or to the start of the
+    /// program if it has none.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), Bf2cError> {
+        self.pc = match snapshot.offset {
+            Some(offset) => self
+                .spans
+                .iter()
+                .position(|span| span.start == offset)
+                .ok_or_else(|| Bf2cError::Invalid(format!("no step starts at offset {}, cannot resume there", offset)))?,
+            None => 0,
+        };
+        self.tape = snapshot.tape;
+        if let Some(journal) = self.journal.as_mut() {
+            journal.clear();
+        }
+        Ok(())
+    }
+
+    /// Runs the program to completion, or until one of `limits` is hit.
+    pub fn run<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W, limits: &Limits) -> Result<(), Bf2cError> {
+        let deadline = limits.timeout.map(|timeout| Instant::now() + timeout);
+        while !self.is_finished() {
+            self.check_limits(limits, deadline)?;
+            self.step(input, output)?;
+        }
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.flush()?;
+        }
+        output.flush().map_err(|e| Bf2cError::io("write output", e))
+    }
+
+    /// Fails if the next step would exceed `limits`, given the `deadline`
+    /// computed from its timeout when the run started.
+    pub(crate) fn check_limits(&self, limits: &Limits, deadline: Option<Instant>) -> Result<(), Bf2cError> {
+        let Some(span) = self.span() else {
+            return Ok(());
+        };
+        if let Some(max) = limits.max_steps.filter(|&max| self.steps >= max) {
+            return Err(Bf2cError::Limit(format!("step limit of {} exceeded at offset {}", max, span.start)));
+        }
+        if let Some(deadline) = deadline {
+            if self.steps.is_multiple_of(CLOCK_CHECK_INTERVAL) && Instant::now() >= deadline {
+                return Err(Bf2cError::Limit(format!(
+                    "time limit of {:?} exceeded at offset {}",
+                    limits.timeout.unwrap_or_default(),
+                    span.start
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes the next step. Does nothing once the program has finished.
+    pub fn step<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W) -> Result<(), Bf2cError> {
+        self.watch_hit = None;
+        if self.is_finished() {
+            return Ok(());
+        }
+        let watching = !self.watches.is_empty();
+        let tracked = self.journal.is_some() || self.activity.is_some() || watching;
+        let written = if tracked { self.written_cells() } else { Vec::new() };
+        let offset = self.spans[self.pc].start;
+        let access = if watching { self.watched_access(&written) } else { None };
+        let entry = self.journal.is_some().then(|| {
+            let cells = written.iter().map(|&cell| (cell, self.tape.cells[cell])).collect();
+            Entry { pc: self.pc, ptr: self.tape.ptr, cells }
+        });
+        let result = match self.tracer.take() {
+            Some(mut tracer) => {
+                let result = self.traced_step(&mut tracer, input, output);
+                self.tracer = Some(tracer);
+                result
+            }
+            None => self.execute(input, output),
+        };
+        if let (Ok(()), Some(entry), Some(journal)) = (&result, entry, self.journal.as_mut()) {
+            journal.push(entry);
+        }
+        if let (Ok(()), Some(activity)) = (&result, self.activity.as_mut()) {
+            activity.record(&written, self.tape.ptr);
+        }
+        if let (Ok(()), Some((cell, write, old))) = (&result, access) {
+            self.watch_hit = Some(WatchHit { cell, write, offset, old, new: self.tape.cells[cell] });
+        }
+        result
+    }
+
+    fn traced_step<R: Read, W: Write>(&mut self, tracer: &mut Tracer, input: &mut R, output: &mut W) -> Result<(), Bf2cError> {
+        let Some(span) = self.span() else {
+            return Ok(());
+        };
+        let ptr = self.tape.ptr;
+        let event = |kind, value| Event { kind, offset: span.start, ptr, value };
+        match self.ops[self.pc] {
+            Op::LoopStart(_) if self.tape.current() != 0 => tracer.record(event(EventKind::LoopEnter, None))?,
+            Op::LoopEnd(_) if self.tape.current() == 0 => tracer.record(event(EventKind::LoopExit, None))?,
+            Op::LoopStart(_) | Op::LoopEnd(_) => {}
+            _ => tracer.record(event(EventKind::Step, None))?,
+        }
+        let mut input = Recording::new(input);
+        let mut output = Recording::new(output);
+        self.execute(&mut input, &mut output)?;
+        for &byte in &input.bytes {
+            tracer.record(event(EventKind::Input, Some(byte)))?;
+        }
+        for &byte in &output.bytes {
+            tracer.record(event(EventKind::Output, Some(byte)))?;
+        }
+        Ok(())
+    }
+
+    fn execute<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W) -> Result<(), Bf2cError> {
+        let Some(op) = self.ops.get(self.pc) else {
+            return Ok(());
+        };
+        let mut next = self.pc + 1;
+        match op {
+            Op::Symbol(token) => match token {
+                BfSymbol::Left => self.tape.shift(-1)?,
+                BfSymbol::Right => self.tape.shift(1)?,
+                BfSymbol::Plus => self.tape.set(self.tape.current().wrapping_add(1)),
+                BfSymbol::Minus => self.tape.set(self.tape.current().wrapping_sub(1)),
+                BfSymbol::Period => write_byte(output, self.tape.current())?,
+                BfSymbol::Comma => self.tape.set(read_byte(input)?),
+                BfSymbol::Debug => eprintln!("{}", debug_line(&self.tape)),
+                BfSymbol::OpenBracket | BfSymbol::CloseBracket => unreachable!("brackets are lowered to jumps"),
+            },
+            Op::Stmt(stmt) => exec_stmt(stmt, &mut self.tape, input, output)?,
+            Op::LoopStart(end) => {
+                if self.tape.current() == 0 {
+                    next = end + 1;
+                }
+            }
+            Op::LoopEnd(start) => {
+                if self.tape.current() != 0 {
+                    next = start + 1;
+                }
+            }
+        }
+        self.hits[self.pc] += 1;
+        self.pc = next;
+        self.steps += 1;
+        Ok(())
+    }
+}
+
+/// Everything a non-interactive run produced.
+pub struct Output {
+    pub stdout: Vec<u8>,
+    /// IR statements executed.
+    pub steps: u64,
+    pub tape: Tape,
+}
+
+/// Runs `program` over the optimized IR with `input` as its stdin and
+/// captures what it writes.
+pub fn run(program: &str, input: &[u8]) -> Result<Output, Bf2cError> {
+    run_with_limits(program, input, &Limits::default())
+}
+
+/// Like `run`, but aborts once one of `limits` is hit.
+pub fn run_with_limits(program: &str, input: &[u8], limits: &Limits) -> Result<Output, Bf2cError> {
+    let mut machine = Machine::from_source(program, true, &ParseOptions::default())?;
+    let mut stdout = Vec::new();
+    machine.run(&mut &input[..], &mut stdout, limits)?;
+    Ok(Output { stdout, steps: machine.steps, tape: machine.tape })
+}
+
+fn flatten(
+    prog: &Prog,
+    ranges: &[Range<usize>],
+    next: &mut usize,
+    ops: &mut Vec<Op>,
+    token_ranges: &mut Vec<Range<usize>>,
+) {
+    for stmt in prog {
+        let range = ranges[*next].clone();
+        *next += 1;
+        match stmt {
+            Stmt::Loop(body) => {
+                let start = ops.len();
+                ops.push(Op::LoopStart(0));
+                token_ranges.push(range.start..range.start + 1);
+                flatten(body, ranges, next, ops, token_ranges);
+                ops[start] = Op::LoopStart(ops.len());
+                ops.push(Op::LoopEnd(start));
+                token_ranges.push(range.end - 1..range.end);
+            }
+            _ => {
+                ops.push(Op::Stmt(stmt.clone()));
+                token_ranges.push(range);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bf2c::{parse, parse_with_spans, ParseOptions};
+    use super::super::localop::{optimize, optimize_with_ranges};
+    use super::super::watch::WatchKind;
+    use super::super::error::Bf2cError;
+    use super::{debug_line, run, run_prog, run_symbols, Limits, Machine, Tape};
+    use std::time::Duration;
+
+    const HELLO: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+    /// Runs `src` through both execution paths and checks that they agree.
+    fn run_both(src: &str, input: &[u8]) -> (Vec<u8>, Tape) {
+        let tokens = parse(src, true).unwrap();
+        let mut raw_tape = Tape::new();
+        let mut raw_out = Vec::new();
+        run_symbols(&tokens, &mut raw_tape, &mut &input[..], &mut raw_out).unwrap();
+
+        let mut opt_tape = Tape::new();
+        let mut opt_out = Vec::new();
+        run_prog(&optimize(&tokens), &mut opt_tape, &mut &input[..], &mut opt_out).unwrap();
+
+        assert_eq!(raw_out, opt_out);
+        assert_eq!(raw_tape.ptr, opt_tape.ptr);
+        assert_eq!(raw_tape.cells, opt_tape.cells);
+        (raw_out, raw_tape)
+    }
+
+    #[test]
+    fn hello_world() {
+        let (out, _) = run_both(HELLO, b"");
+        assert_eq!(out, b"Hello World!\n");
+    }
+
+    #[test]
+    fn echo_input() {
+        let (out, _) = run_both(",.,,.", b"abc");
+        assert_eq!(out, b"ac");
+    }
+
+    #[test]
+    fn eof_reads_255() {
+        let (_, tape) = run_both(",", b"");
+        assert_eq!(tape.cells[0], 255);
+    }
+
+    #[test]
+    fn multiplication_loop_with_odd_decrement() {
+        // 7 * 3^-1 (mod 256) iterations, each adding 2 to cell 2
+        let (_, tape) = run_both("+++++++[--->>++<<]", b"");
+        assert_eq!(tape.cells[0], 0);
+        assert_eq!(tape.cells[2], 7u8.wrapping_mul(171).wrapping_mul(2));
+    }
+
+    #[test]
+    fn scan_loops() {
+        let (_, tape) = run_both("+>+>+>>+<<<<[>]>[<]", b"");
+        assert_eq!(tape.ptr, 3);
+    }
+
+    #[test]
+    fn wrapping_arithmetic() {
+        let (_, tape) = run_both("-", b"");
+        assert_eq!(tape.cells[0], 255);
+    }
+
+    #[test]
+    fn pointer_underflow_is_an_error() {
+        let tokens = parse("<", true).unwrap();
+        let mut out = Vec::new();
+        assert!(matches!(run_symbols(&tokens, &mut Tape::new(), &mut &b""[..], &mut out), Err(Bf2cError::Runtime(_))));
+        assert!(matches!(run_prog(&optimize(&tokens), &mut Tape::new(), &mut &b""[..], &mut out), Err(Bf2cError::Runtime(_))));
+    }
+
+    #[test]
+    fn machine_matches_run_symbols() {
+        let (tokens, spans) = parse_with_spans(HELLO, true, &ParseOptions::default()).unwrap();
+        let (prog, ranges) = optimize_with_ranges(&tokens);
+        for mut machine in [
+            Machine::from_symbols(&tokens, &spans).unwrap(),
+            Machine::from_prog(&prog, &ranges, &spans),
+        ] {
+            let mut out = Vec::new();
+            while !machine.is_finished() {
+                machine.step(&mut &b""[..], &mut out).unwrap();
+            }
+            assert_eq!(out, b"Hello World!\n");
+        }
+    }
+
+    #[test]
+    fn machine_spans() {
+        let src = " ++ [-]";
+        let (tokens, spans) = parse_with_spans(src, true, &ParseOptions::default()).unwrap();
+        let (prog, ranges) = optimize_with_ranges(&tokens);
+        let mut machine = Machine::from_prog(&prog, &ranges, &spans);
+        assert_eq!(machine.span(), Some(1..3));
+        machine.step(&mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(machine.span(), Some(4..7));
+        machine.step(&mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(machine.span(), None);
+        assert_eq!(machine.tape.cells[0], 0);
+    }
+
+    #[test]
+    fn step_limit_reports_offset() {
+        let (tokens, spans) = parse_with_spans("+ [ ]", true, &ParseOptions::default()).unwrap();
+        let (prog, ranges) = optimize_with_ranges(&tokens);
+        let mut machine = Machine::from_prog(&prog, &ranges, &spans);
+        let limits = Limits { max_steps: Some(10), timeout: None };
+        let err = machine.run(&mut &b""[..], &mut Vec::new(), &limits).unwrap_err();
+        assert!(matches!(&err, Bf2cError::Limit(message) if message == "step limit of 10 exceeded at offset 4"));
+    }
+
+    #[test]
+    fn timeout_aborts_infinite_loop() {
+        let (tokens, spans) = parse_with_spans("+[]", true, &ParseOptions::default()).unwrap();
+        let mut machine = Machine::from_symbols(&tokens, &spans).unwrap();
+        let limits = Limits { max_steps: None, timeout: Some(Duration::from_millis(10)) };
+        let err = machine.run(&mut &b""[..], &mut Vec::new(), &limits).unwrap_err();
+        assert!(matches!(&err, Bf2cError::Limit(message) if message.starts_with("time limit of 10ms exceeded at offset")));
+    }
+
+    #[test]
+    fn limits_allow_finished_programs() {
+        let (tokens, spans) = parse_with_spans("+++[-]", true, &ParseOptions::default()).unwrap();
+        let mut machine = Machine::from_symbols(&tokens, &spans).unwrap();
+        // exactly as many steps as the program needs
+        let limits = Limits { max_steps: Some(10), timeout: None };
+        machine.run(&mut &b""[..], &mut Vec::new(), &limits).unwrap();
+        assert_eq!(machine.steps, 10);
+    }
+
+    #[test]
+    fn run_captures_output() {
+        let output = run(",[.,]", b"echo\0").unwrap();
+        assert_eq!(output.stdout, b"echo");
+        assert_eq!(output.tape.cells[0], 0);
+        assert!(output.steps > 0);
+        // end of input reads as 255, so `,+` loops stop there
+        assert_eq!(run(",+[-.,+]", b"ab").unwrap().stdout, b"ab");
+    }
+
+    #[test]
+    fn debug_line_format() {
+        let mut tape = Tape::new();
+        tape.cells[1] = 3;
+        tape.ptr = 1;
+        assert_eq!(debug_line(&tape), "ptr=1: 0 [3] 0 0 0 0");
+        tape.ptr = 10;
+        assert_eq!(debug_line(&tape), "ptr=10: 0 0 0 0 [0] 0 0 0 0");
+    }
+
+    #[test]
+    fn step_back_restores_state() {
+        let src = "+++[->++<]>,";
+        let mut machine = Machine::from_source(src, true, &ParseOptions::default()).unwrap();
+        machine.set_journal(100);
+        let mut input = &b"x"[..];
+        let mut states = Vec::new();
+        while !machine.is_finished() {
+            states.push((machine.pc, machine.tape.ptr, machine.tape.cells[..2].to_vec()));
+            machine.step(&mut input, &mut Vec::new()).unwrap();
+        }
+        assert_eq!(machine.tape.cells[1], b'x');
+        while let Some(state) = states.pop() {
+            assert!(machine.step_back());
+            assert_eq!((machine.pc, machine.tape.ptr, machine.tape.cells[..2].to_vec()), state);
+        }
+        assert!(!machine.step_back());
+        assert_eq!(machine.steps, 0);
+    }
+
+    #[test]
+    fn journal_is_bounded() {
+        let mut machine = Machine::from_source("+++++", false, &ParseOptions::default()).unwrap();
+        machine.set_journal(2);
+        machine.run(&mut &b""[..], &mut Vec::new(), &Limits::default()).unwrap();
+        assert!(machine.step_back());
+        assert!(machine.step_back());
+        assert!(!machine.step_back());
+        assert_eq!(machine.tape.cells[0], 3);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut machine = Machine::from_source("+>+++[<+>-]<.", true, &ParseOptions::default()).unwrap();
+        machine.set_watch(0, WatchKind::Write);
+        let mut hits = Vec::new();
+        while !machine.is_finished() {
+            machine.step(&mut &b""[..], &mut Vec::new()).unwrap();
+            hits.extend(machine.watch_hit());
+        }
+        let describe: Vec<String> = hits.iter().map(|hit| hit.to_string()).collect();
+        assert_eq!(describe, ["cell 0 written at offset 0: 0 -> 1", "cell 0 written at offset 5: 1 -> 4"]);
+
+        let mut machine = Machine::from_source("+>+<[>]", true, &ParseOptions::default()).unwrap();
+        machine.set_watch(2, WatchKind::Read);
+        machine.run(&mut &b""[..], &mut Vec::new(), &Limits::default()).unwrap();
+        assert_eq!(machine.watch_hit().map(|hit| (hit.offset, hit.write)), Some((4, false)));
+    }
+}
@@ -0,0 +1,362 @@
+//! Just-in-time compilation of the IR to machine code with Cranelift, for
+//! running programs at native speed without a C compiler installed.
+//!
+//! The compiled code behaves like `run_prog`: cells wrap at 8 bits, moving
+//! off the tape is an error, and I/O and `#` go through the same helpers.
+
+use std::io::{Read, Write};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::slice;
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Block, FuncRef, InstBuilder, MemFlags, UserFuncName, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+use tracing::info;
+
+use super::error::Bf2cError;
+use super::interp::{debug_window, read_byte, write_byte, Tape};
+use super::localop::{inverse_mod_256, Prog, Stmt};
+use super::stopwatch::Stopwatch;
+
+/// State shared with the callbacks of the compiled code.
+struct Context<'a> {
+    input: &'a mut dyn Read,
+    output: &'a mut dyn Write,
+    cells: *const u8,
+    len: usize,
+    /// Why the compiled code stopped early.
+    error: Option<Bf2cError>,
+}
+
+/// Signature of the compiled program: cells, pointer and context in, the
+/// final pointer out, or -1 after a callback stored an error.
+type Entry = unsafe extern "C" fn(*mut u8, i64, *mut Context) -> i64;
+
+/// Compiles `prog` to machine code and runs it on `tape`.
+pub fn run_jit<R: Read, W: Write>(prog: &Prog, tape: &mut Tape, input: &mut R, output: &mut W) -> Result<(), Bf2cError> {
+    let start = Stopwatch::start();
+    let mut module = jit_module()?;
+    let entry = compile(&mut module, prog, tape.cells.len())?;
+    info!(statements = prog.len(), elapsed = ?start.elapsed(), "compiled to machine code");
+    let mut context = Context { input, output, cells: tape.cells.as_ptr(), len: tape.cells.len(), error: None };
+    // SAFETY: the code checks every access against the tape length, and the
+    // callbacks only read the cells between two accesses
+    let ptr = unsafe {
+        let run: Entry = std::mem::transmute(module.get_finalized_function(entry));
+        run(tape.cells.as_mut_ptr(), tape.ptr as i64, &mut context)
+    };
+    let error = context.error.take();
+    // SAFETY: nothing refers to the compiled code any more
+    unsafe { module.free_memory() };
+    if let Some(e) = error {
+        return Err(e);
+    }
+    tape.ptr = ptr as usize;
+    output.flush().map_err(|e| Bf2cError::io("write output", e))
+}
+
+fn jit_module() -> Result<JITModule, Bf2cError> {
+    let mut flags = settings::builder();
+    flags.set("use_colocated_libcalls", "false").expect("known setting");
+    flags.set("is_pic", "false").expect("known setting");
+    flags.set("opt_level", "speed").expect("known setting");
+    let isa = cranelift_native::builder()
+        .map_err(|e| Bf2cError::Compile(format!("this machine is not supported by the JIT: {}", e)))?
+        .finish(settings::Flags::new(flags))
+        .map_err(|e| Bf2cError::Compile(format!("cannot set up the JIT: {}", e)))?;
+    if isa.pointer_type() != types::I64 {
+        return Err(Bf2cError::Compile("the JIT needs a 64-bit machine".to_string()));
+    }
+    let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+    builder.symbol("bf_output", bf_output as *const u8);
+    builder.symbol("bf_input", bf_input as *const u8);
+    builder.symbol("bf_debug", bf_debug as *const u8);
+    builder.symbol("bf_off_tape", bf_off_tape as *const u8);
+    Ok(JITModule::new(builder))
+}
+
+fn compile(module: &mut JITModule, prog: &Prog, len: usize) -> Result<cranelift_module::FuncId, Bf2cError> {
+    let failed = |e: cranelift_module::ModuleError| Bf2cError::Compile(format!("JIT compilation failed: {}", e));
+    let mut signature = module.make_signature();
+    signature.params.extend([AbiParam::new(types::I64); 3]);
+    signature.returns.push(AbiParam::new(types::I64));
+    let entry = module.declare_function("bf_main", Linkage::Local, &signature).map_err(failed)?;
+
+    let mut import = |name: &str, params: &[types::Type], ret: Option<types::Type>| {
+        let mut signature = module.make_signature();
+        signature.params.extend(params.iter().map(|&ty| AbiParam::new(ty)));
+        signature.returns.extend(ret.map(AbiParam::new));
+        module.declare_function(name, Linkage::Import, &signature).map_err(failed)
+    };
+    let output = import("bf_output", &[types::I64, types::I8], Some(types::I8))?;
+    let input = import("bf_input", &[types::I64], Some(types::I32))?;
+    let debug = import("bf_debug", &[types::I64, types::I64], None)?;
+    let off_tape = import("bf_off_tape", &[types::I64, types::I64], None)?;
+
+    let mut ctx = module.make_context();
+    ctx.func.signature = signature;
+    ctx.func.name = UserFuncName::user(0, entry.as_u32());
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let callbacks = Callbacks {
+            output: module.declare_func_in_func(output, builder.func),
+            input: module.declare_func_in_func(input, builder.func),
+            debug: module.declare_func_in_func(debug, builder.func),
+            off_tape: module.declare_func_in_func(off_tape, builder.func),
+        };
+        let start = builder.create_block();
+        builder.append_block_params_for_function_params(start);
+        builder.switch_to_block(start);
+        let params = builder.block_params(start).to_vec();
+        let ptr = Variable::from_u32(0);
+        builder.declare_var(ptr, types::I64);
+        builder.def_var(ptr, params[1]);
+        let fail = builder.create_block();
+        let off_tape = builder.create_block();
+        builder.append_block_param(off_tape, types::I64);
+        let mut emitter = Emitter { builder, callbacks, cells: params[0], context: params[2], ptr, len: len as i64, fail, off_tape };
+        emitter.block(prog);
+        emitter.finish();
+    }
+    module.define_function(entry, &mut ctx).map_err(failed)?;
+    module.clear_context(&mut ctx);
+    module.finalize_definitions().map_err(failed)?;
+    Ok(entry)
+}
+
+struct Callbacks {
+    output: FuncRef,
+    input: FuncRef,
+    debug: FuncRef,
+    off_tape: FuncRef,
+}
+
+struct Emitter<'a> {
+    builder: FunctionBuilder<'a>,
+    callbacks: Callbacks,
+    cells: Value,
+    context: Value,
+    ptr: Variable,
+    len: i64,
+    /// Returns -1, after a callback stored an error.
+    fail: Block,
+    /// Reports its parameter, a cell off the tape, and fails.
+    off_tape: Block,
+}
+
+impl Emitter<'_> {
+    fn block(&mut self, prog: &Prog) {
+        for stmt in prog {
+            match stmt {
+                Stmt::Add(delta) => {
+                    let ptr = self.builder.use_var(self.ptr);
+                    let value = self.load(ptr);
+                    let sum = self.builder.ins().iadd_imm(value, *delta as u8 as i64);
+                    self.store(ptr, sum);
+                }
+                Stmt::Move(distance) => self.shift(*distance),
+                Stmt::Output(count) => {
+                    for _ in 0..*count {
+                        let ptr = self.builder.use_var(self.ptr);
+                        let value = self.load(ptr);
+                        let call = self.builder.ins().call(self.callbacks.output, &[self.context, value]);
+                        let status = self.builder.inst_results(call)[0];
+                        self.fail_if_nonzero(status);
+                    }
+                }
+                Stmt::Input(count) => {
+                    for _ in 0..*count {
+                        let call = self.builder.ins().call(self.callbacks.input, &[self.context]);
+                        let byte = self.builder.inst_results(call)[0];
+                        let failed = self.builder.ins().icmp_imm(IntCC::SignedLessThan, byte, 0);
+                        self.fail_if_nonzero(failed);
+                        let byte = self.builder.ins().ireduce(types::I8, byte);
+                        let ptr = self.builder.use_var(self.ptr);
+                        self.store(ptr, byte);
+                    }
+                }
+                Stmt::Loop(body) => self.while_nonzero(|emitter| emitter.block(body)),
+                Stmt::ZeroLoop => {
+                    let ptr = self.builder.use_var(self.ptr);
+                    let zero = self.builder.ins().iconst(types::I8, 0);
+                    self.store(ptr, zero);
+                }
+                Stmt::ScanLoop(direction) => self.while_nonzero(|emitter| emitter.shift(*direction)),
+                Stmt::MultiplicationLoop(decrement, effects) => {
+                    let ptr = self.builder.use_var(self.ptr);
+                    let value = self.load(ptr);
+                    let body = self.builder.create_block();
+                    let done = self.builder.create_block();
+                    // nothing happens, and nothing is checked, on a zero cell
+                    self.builder.ins().brif(value, body, &[], done, &[]);
+                    self.builder.switch_to_block(body);
+                    let iterations = self.builder.ins().imul_imm(value, inverse_mod_256(*decrement as u8) as i64);
+                    for &(offset, factor) in effects {
+                        let target = self.builder.ins().iadd_imm(ptr, offset as i64);
+                        self.check(target);
+                        let cell = self.load(target);
+                        let product = self.builder.ins().imul_imm(iterations, factor as u8 as i64);
+                        let sum = self.builder.ins().iadd(cell, product);
+                        self.store(target, sum);
+                    }
+                    let zero = self.builder.ins().iconst(types::I8, 0);
+                    self.store(ptr, zero);
+                    self.builder.ins().jump(done, &[]);
+                    self.builder.switch_to_block(done);
+                }
+                Stmt::Debug => {
+                    let ptr = self.builder.use_var(self.ptr);
+                    self.builder.ins().call(self.callbacks.debug, &[self.context, ptr]);
+                }
+            }
+        }
+    }
+
+    /// Returns the pointer, and emits the shared failure paths.
+    fn finish(mut self) {
+        let ptr = self.builder.use_var(self.ptr);
+        self.builder.ins().return_(&[ptr]);
+        self.builder.switch_to_block(self.off_tape);
+        let target = self.builder.block_params(self.off_tape)[0];
+        self.builder.ins().call(self.callbacks.off_tape, &[self.context, target]);
+        self.builder.ins().jump(self.fail, &[]);
+        self.builder.switch_to_block(self.fail);
+        let failed = self.builder.ins().iconst(types::I64, -1);
+        self.builder.ins().return_(&[failed]);
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+    }
+
+    fn while_nonzero(&mut self, body: impl FnOnce(&mut Self)) {
+        let header = self.builder.create_block();
+        let inside = self.builder.create_block();
+        let after = self.builder.create_block();
+        self.builder.ins().jump(header, &[]);
+        self.builder.switch_to_block(header);
+        let ptr = self.builder.use_var(self.ptr);
+        let value = self.load(ptr);
+        self.builder.ins().brif(value, inside, &[], after, &[]);
+        self.builder.switch_to_block(inside);
+        body(self);
+        self.builder.ins().jump(header, &[]);
+        self.builder.switch_to_block(after);
+    }
+
+    fn shift(&mut self, distance: i32) {
+        let ptr = self.builder.use_var(self.ptr);
+        let target = self.builder.ins().iadd_imm(ptr, distance as i64);
+        self.check(target);
+        self.builder.def_var(self.ptr, target);
+    }
+
+    /// Continues only if `target` is a cell of the tape. Negative targets
+    /// are huge as unsigned numbers.
+    fn check(&mut self, target: Value) {
+        let inside = self.builder.ins().icmp_imm(IntCC::UnsignedLessThan, target, self.len);
+        let next = self.builder.create_block();
+        self.builder.ins().brif(inside, next, &[], self.off_tape, &[target]);
+        self.builder.switch_to_block(next);
+    }
+
+    fn fail_if_nonzero(&mut self, condition: Value) {
+        let next = self.builder.create_block();
+        self.builder.ins().brif(condition, self.fail, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    fn load(&mut self, index: Value) -> Value {
+        let address = self.builder.ins().iadd(self.cells, index);
+        self.builder.ins().load(types::I8, MemFlags::trusted(), address, 0)
+    }
+
+    fn store(&mut self, index: Value, value: Value) {
+        let address = self.builder.ins().iadd(self.cells, index);
+        self.builder.ins().store(MemFlags::trusted(), value, address, 0);
+    }
+}
+
+/// Runs `callback` on the context, storing its error. Panics must not
+/// unwind into the compiled code.
+fn with_context<T>(context: *mut Context, failed: T, callback: impl FnOnce(&mut Context) -> Result<T, Bf2cError>) -> T {
+    // SAFETY: the compiled code passes on the context it was given
+    let context = unsafe { &mut *context };
+    match catch_unwind(AssertUnwindSafe(|| callback(context))) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            context.error = Some(e);
+            failed
+        }
+        Err(_) => {
+            context.error = Some(Bf2cError::Runtime("panic during I/O of the compiled program".to_string()));
+            failed
+        }
+    }
+}
+
+extern "C" fn bf_output(context: *mut Context, byte: u8) -> u8 {
+    with_context(context, 1, |context| write_byte(&mut context.output, byte).map(|_| 0))
+}
+
+extern "C" fn bf_input(context: *mut Context) -> i32 {
+    with_context(context, -1, |context| read_byte(&mut context.input).map(i32::from))
+}
+
+extern "C" fn bf_debug(context: *mut Context, ptr: i64) {
+    with_context(context, (), |context| {
+        // SAFETY: the compiled code is not touching the cells during the call
+        let cells = unsafe { slice::from_raw_parts(context.cells, context.len) };
+        eprintln!("{}", debug_window(cells, ptr as usize));
+        Ok(())
+    })
+}
+
+extern "C" fn bf_off_tape(context: *mut Context, target: i64) {
+    with_context(context, (), |_| Err(Bf2cError::Runtime(format!("data pointer moved out of the tape (cell {})", target))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bf2c::parse;
+    use super::super::error::Bf2cError;
+    use super::super::interp::{run_prog, Tape};
+    use super::super::localop::optimize;
+    use super::run_jit;
+
+    fn both(source: &str, input: &[u8]) -> (Result<Vec<u8>, String>, Result<Vec<u8>, String>) {
+        let prog = optimize(&parse(source, true).unwrap());
+        let run = |jit: bool| {
+            let mut output = Vec::new();
+            let mut tape = Tape::new();
+            let result = if jit {
+                run_jit(&prog, &mut tape, &mut &input[..], &mut output)
+            } else {
+                run_prog(&prog, &mut tape, &mut &input[..], &mut output)
+            };
+            result.map(|_| output).map_err(|e| e.to_string())
+        };
+        (run(true), run(false))
+    }
+
+    #[test]
+    fn matches_the_interpreter() {
+        let hello = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.";
+        for (source, input) in [(hello, &b""[..]), (",[.,]", b"echo\0"), ("++++[>+++<-]>[--->++<]>>+>+[<]>[.>]", b""), (",[->+++++<]>.", b"\x03")] {
+            let (jit, interpreted) = both(source, input);
+            assert_eq!(jit, interpreted, "{}", source);
+        }
+    }
+
+    #[test]
+    fn reports_moves_off_the_tape() {
+        let (jit, interpreted) = both(">[-<<+>>]<<<", b"");
+        assert_eq!(jit, interpreted);
+        assert_eq!(jit.unwrap_err(), "data pointer moved out of the tape (cell -2)");
+        let prog = optimize(&parse("+[>+]", true).unwrap());
+        let error = run_jit(&prog, &mut Tape::new(), &mut &b""[..], &mut Vec::new()).unwrap_err();
+        assert!(matches!(error, Bf2cError::Runtime(_)));
+    }
+}
@@ -0,0 +1,85 @@
+//! Bounded undo log that lets the interpreter's `Machine` step backwards.
+
+use std::collections::VecDeque;
+
+/// State overwritten by one step.
+#[derive(Debug, Clone)]
+pub(crate) struct Entry {
+    pub(crate) pc: usize,
+    pub(crate) ptr: usize,
+    /// `(cell, old value)` for every cell the step may write.
+    pub(crate) cells: Vec<(usize, u8)>,
+}
+
+/// Keeps the undo entries of the last `capacity` steps, dropping the oldest
+/// once full.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Self {
+        Journal { entries: VecDeque::new(), capacity }
+    }
+
+    /// Number of steps that can currently be undone.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(crate) fn push(&mut self, entry: Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop_back()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, Journal};
+
+    fn entry(pc: usize) -> Entry {
+        Entry { pc, ptr: 0, cells: Vec::new() }
+    }
+
+    #[test]
+    fn drops_oldest_entries() {
+        let mut journal = Journal::new(2);
+        for pc in 0..3 {
+            journal.push(entry(pc));
+        }
+        assert_eq!(journal.len(), 2);
+        assert_eq!(journal.pop().unwrap().pc, 2);
+        assert_eq!(journal.pop().unwrap().pc, 1);
+        assert!(journal.pop().is_none());
+    }
+
+    #[test]
+    fn zero_capacity_records_nothing() {
+        let mut journal = Journal::new(0);
+        journal.push(entry(0));
+        assert!(journal.is_empty());
+    }
+}
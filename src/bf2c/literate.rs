@@ -0,0 +1,60 @@
+//! Literate sources: Markdown-like text where only fenced code blocks and
+//! lines quoted with `>` hold the program, and everything else is prose.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Blanks out everything in `buf` but the code, byte for byte, so offsets in
+/// the result are offsets in `buf`. Fence lines and the `>` quote markers
+/// are blanked too. Line breaks are kept.
+pub(crate) fn code_only(buf: &str) -> String {
+    let mut out = Vec::with_capacity(buf.len());
+    let mut fenced = false;
+    for line in buf.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let code = if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fenced = !fenced;
+            ""
+        } else if fenced {
+            line
+        } else if let Some(quoted) = trimmed.strip_prefix('>') {
+            out.resize(out.len() + line.len() - quoted.len(), b' ');
+            out.extend_from_slice(quoted.as_bytes());
+            continue;
+        } else {
+            ""
+        };
+        out.extend_from_slice(code.as_bytes());
+        // prose, keeping the line break so lines and columns still match
+        let prose = &line[code.len()..];
+        out.extend(prose.bytes().map(|b| if b == b'\n' { b'\n' } else { b' ' }));
+    }
+    String::from_utf8(out).expect("only ASCII spaces replace text")
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use super::super::bf2c::{parse_with_options, BfSymbol, ParseOptions};
+    use super::code_only;
+
+    #[test]
+    fn keeps_fenced_and_quoted_code() {
+        let source = "Add two, then print.\n```bf\n++\n```\nNot code: -\n> .\n";
+        assert_eq!(code_only(source), "                    \n     \n++\n   \n           \n  .\n");
+        assert_eq!(code_only(source).len(), source.len());
+    }
+
+    #[test]
+    fn offsets_refer_to_the_original() {
+        let options = ParseOptions { literate: true, ..ParseOptions::default() };
+        let source = "Café [loop]\n> +[\n";
+        let e = parse_with_options(source, true, &options).unwrap_err();
+        assert_eq!(e.to_string(), "'[' opened at line 2, column 4 is never closed");
+        let (tokens, offsets) = parse_with_options(source, false, &options).unwrap();
+        assert_eq!(tokens, vec![BfSymbol::Plus, BfSymbol::OpenBracket]);
+        assert_eq!(offsets, vec![15, 16]);
+    }
+}
@@ -0,0 +1,308 @@
+//! Local optimizations over the Brainfuck token stream.
+//!
+//! Lowers the flat `BfSymbol` sequence into the IR described in
+//! `docs/03-bf2c_op1.md`: runs of arithmetic, movement and I/O are coalesced,
+//! and loops are classified as zero, scan or multiplication loops where
+//! that can be done safely.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+
+use tracing::{field, info, info_span};
+
+use super::bf2c::{BfSymbol, Span};
+use super::stopwatch::Stopwatch;
+
+pub type Prog = Vec<Stmt>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Stmt {
+    /// Add `delta` to the current cell.
+    Add(i32),
+    /// Move the data pointer by `distance` cells.
+    Move(i32),
+    /// Output the current cell `count` times.
+    Output(u32),
+    /// Read `count` bytes, keeping only the last one in the current cell.
+    Input(u32),
+    /// General `[...]` loop.
+    Loop(Prog),
+    /// `[-]` / `[+]`: set the current cell to zero.
+    ZeroLoop,
+    /// `[>]` / `[<]`: move by `direction` until a zero cell is reached.
+    ScanLoop(i32),
+    /// Linear loop consuming the current cell. The control cell is decreased
+    /// by `decrement` (always odd) per iteration, and every `(offset, factor)`
+    /// pair adds `factor` per iteration to the cell at `offset`. Both are kept
+    /// whole, so that they can be reduced modulo any cell size.
+    MultiplicationLoop(i32, Vec<(i32, i32)>),
+    /// `#`: dump the tape around the pointer.
+    Debug,
+}
+
+/// A transformation of the IR, registered with `TranspilerBuilder::pass`.
+/// Passes run in registration order on the output of `optimize`, before the
+/// program is emitted, and must keep its behavior unchanged.
+pub trait IrPass: fmt::Debug + Send + Sync {
+    /// Short name, used in logs.
+    fn name(&self) -> &str;
+
+    fn run(&self, prog: &mut Prog);
+}
+
+/// Lower a well-formed token stream into the local optimization IR.
+///
+/// Brackets must already have been verified by `parse`.
+pub fn optimize(tokens: &[BfSymbol]) -> Prog {
+    optimize_with_ranges(tokens).0
+}
+
+/// Like `optimize`, but also returns the range of tokens each statement was
+/// built from. Ranges are listed in pre-order: a loop comes before the
+/// statements of its body, and a loop's range includes both brackets.
+pub fn optimize_with_ranges(tokens: &[BfSymbol]) -> (Prog, Vec<Range<usize>>) {
+    let span = info_span!("optimize", tokens = tokens.len(), statements = field::Empty).entered();
+    let start = Stopwatch::start();
+    let mut pos = 0;
+    let mut ranges = Vec::new();
+    let prog = optimize_block(tokens, &mut pos, &mut ranges);
+    debug_assert_eq!(pos, tokens.len(), "unbalanced brackets reached the optimizer");
+    span.record("statements", ranges.len());
+    info!(tokens = tokens.len(), statements = ranges.len(), elapsed = ?start.elapsed(), "optimized");
+    (prog, ranges)
+}
+
+/// Like `optimize_with_ranges`, for tokens from `parse_spanned`: returns the
+/// source span of each statement instead of its token range.
+pub fn optimize_with_spans(tokens: &[(BfSymbol, Span)]) -> (Prog, Vec<Span>) {
+    let symbols: Vec<BfSymbol> = tokens.iter().map(|(symbol, _)| *symbol).collect();
+    let (prog, ranges) = optimize_with_ranges(&symbols);
+    let spans = ranges.into_iter().map(|range| tokens[range.start].1.to(&tokens[range.end - 1].1)).collect();
+    (prog, spans)
+}
+
+fn optimize_block(tokens: &[BfSymbol], pos: &mut usize, ranges: &mut Vec<Range<usize>>) -> Prog {
+    let mut out = Prog::new();
+    while *pos < tokens.len() {
+        let start = *pos;
+        let token = tokens[start];
+        *pos += 1;
+        match token {
+            BfSymbol::Plus => push_add(&mut out, ranges, start, 1),
+            BfSymbol::Minus => push_add(&mut out, ranges, start, -1),
+            BfSymbol::Right => push_move(&mut out, ranges, start, 1),
+            BfSymbol::Left => push_move(&mut out, ranges, start, -1),
+            BfSymbol::Period => match out.last_mut() {
+                Some(Stmt::Output(count)) => {
+                    *count += 1;
+                    extend_last(ranges, start);
+                }
+                _ => push(&mut out, ranges, start, Stmt::Output(1)),
+            },
+            BfSymbol::Comma => match out.last_mut() {
+                Some(Stmt::Input(count)) => {
+                    *count += 1;
+                    extend_last(ranges, start);
+                }
+                _ => push(&mut out, ranges, start, Stmt::Input(1)),
+            },
+            BfSymbol::OpenBracket => {
+                let index = ranges.len();
+                ranges.push(start..start);
+                let body = optimize_block(tokens, pos, ranges);
+                ranges[index].end = *pos;
+                let stmt = classify_loop(body);
+                if !matches!(stmt, Stmt::Loop(_)) {
+                    // the body was folded into the loop statement
+                    ranges.truncate(index + 1);
+                }
+                out.push(stmt);
+            }
+            BfSymbol::CloseBracket => break,
+            BfSymbol::Debug => push(&mut out, ranges, start, Stmt::Debug),
+        }
+    }
+    out
+}
+
+fn push(out: &mut Prog, ranges: &mut Vec<Range<usize>>, start: usize, stmt: Stmt) {
+    out.push(stmt);
+    ranges.push(start..start + 1);
+}
+
+fn extend_last(ranges: &mut [Range<usize>], token: usize) {
+    if let Some(range) = ranges.last_mut() {
+        range.end = token + 1;
+    }
+}
+
+fn push_add(out: &mut Prog, ranges: &mut Vec<Range<usize>>, start: usize, delta: i32) {
+    if let Some(Stmt::Add(acc)) = out.last_mut() {
+        *acc += delta;
+        if *acc == 0 {
+            out.pop();
+            ranges.pop();
+        } else {
+            extend_last(ranges, start);
+        }
+    } else {
+        push(out, ranges, start, Stmt::Add(delta));
+    }
+}
+
+fn push_move(out: &mut Prog, ranges: &mut Vec<Range<usize>>, start: usize, distance: i32) {
+    if let Some(Stmt::Move(acc)) = out.last_mut() {
+        *acc += distance;
+        if *acc == 0 {
+            out.pop();
+            ranges.pop();
+        } else {
+            extend_last(ranges, start);
+        }
+    } else {
+        push(out, ranges, start, Stmt::Move(distance));
+    }
+}
+
+fn classify_loop(body: Prog) -> Stmt {
+    match body.as_slice() {
+        [Stmt::Add(delta)] if delta % 2 != 0 => return Stmt::ZeroLoop,
+        [Stmt::Move(distance)] => return Stmt::ScanLoop(*distance),
+        _ => {}
+    }
+    match multiplication_effects(&body) {
+        Some((decrement, effects)) => Stmt::MultiplicationLoop(decrement, effects),
+        None => Stmt::Loop(body),
+    }
+}
+
+/// Returns the per-iteration decrement of the control cell and the effects on
+/// the other cells if `body` is a balanced loop made of `Add`/`Move` only.
+fn multiplication_effects(body: &Prog) -> Option<(i32, Vec<(i32, i32)>)> {
+    let mut offset = 0;
+    let mut control = 0;
+    let mut effects: Vec<(i32, i32)> = Vec::new();
+    for stmt in body {
+        match stmt {
+            Stmt::Move(distance) => offset += distance,
+            Stmt::Add(delta) if offset == 0 => control += delta,
+            Stmt::Add(delta) => match effects.iter_mut().find(|(o, _)| *o == offset) {
+                Some((_, factor)) => *factor += delta,
+                None => effects.push((offset, *delta)),
+            },
+            _ => return None,
+        }
+    }
+    // an even decrement may never reach zero, see "Termination Safety"
+    let decrement = control.wrapping_neg();
+    if offset != 0 || decrement % 2 == 0 {
+        return None;
+    }
+    effects.retain(|&(_, factor)| factor != 0);
+    Some((decrement, effects))
+}
+
+/// Multiplicative inverse of an odd `value` modulo 256.
+pub fn inverse_mod_256(value: u8) -> u8 {
+    debug_assert!(value % 2 == 1, "only odd values are invertible modulo 256");
+    // Newton's iteration, each step doubles the number of correct bits
+    let mut inv = value;
+    for _ in 0..3 {
+        inv = inv.wrapping_mul(2u8.wrapping_sub(value.wrapping_mul(inv)));
+    }
+    inv
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::super::bf2c::{parse_spanned, parse_without_verification, ParseOptions};
+    use super::{inverse_mod_256, optimize, optimize_with_ranges, optimize_with_spans, Stmt};
+
+    fn opt(src: &str) -> Vec<Stmt> {
+        optimize(&parse_without_verification(src))
+    }
+
+    #[test]
+    fn coalesce_and_cancel() {
+        assert_eq!(opt("+++--+"), vec![Stmt::Add(2)]);
+        assert_eq!(opt(">><>>"), vec![Stmt::Move(3)]);
+        assert_eq!(opt("><><"), vec![]);
+        assert_eq!(opt("....."), vec![Stmt::Output(5)]);
+        assert_eq!(opt(",,,"), vec![Stmt::Input(3)]);
+        assert_eq!(opt("+-+>"), vec![Stmt::Add(1), Stmt::Move(1)]);
+    }
+
+    #[test]
+    fn zero_and_scan_loops() {
+        assert_eq!(opt("[-]"), vec![Stmt::ZeroLoop]);
+        assert_eq!(opt("[+]"), vec![Stmt::ZeroLoop]);
+        assert_eq!(opt("[>]"), vec![Stmt::ScanLoop(1)]);
+        assert_eq!(opt("[<<]"), vec![Stmt::ScanLoop(-2)]);
+    }
+
+    #[test]
+    fn multiplication_loops() {
+        assert_eq!(opt("[->+<]"), vec![Stmt::MultiplicationLoop(1, vec![(1, 1)])]);
+        assert_eq!(
+            opt("[-<+>>+++<]"),
+            vec![Stmt::MultiplicationLoop(1, vec![(-1, 1), (1, 3)])]
+        );
+        assert_eq!(opt("[--->>++<<]"), vec![Stmt::MultiplicationLoop(3, vec![(2, 2)])]);
+    }
+
+    #[test]
+    fn unsafe_loops_are_kept() {
+        assert_eq!(
+            opt("[-->>+<<]"),
+            vec![Stmt::Loop(vec![Stmt::Add(-2), Stmt::Move(2), Stmt::Add(1), Stmt::Move(-2)])]
+        );
+        assert_eq!(
+            opt("[->+<.]"),
+            vec![Stmt::Loop(vec![
+                Stmt::Add(-1),
+                Stmt::Move(1),
+                Stmt::Add(1),
+                Stmt::Move(-1),
+                Stmt::Output(1)
+            ])]
+        );
+    }
+
+    #[test]
+    fn statement_ranges() {
+        let (prog, ranges) = optimize_with_ranges(&parse_without_verification("++>[-]<[->+<.]"));
+        assert_eq!(prog.len(), 5);
+        assert_eq!(ranges, vec![0..2, 2..3, 3..6, 6..7, 7..14, 8..9, 9..10, 10..11, 11..12, 12..13]);
+    }
+
+    #[test]
+    fn statement_spans() {
+        let tokens = parse_spanned("++ x\n[\n  -\n]", &ParseOptions::default()).unwrap();
+        let (_, spans) = optimize_with_spans(&tokens);
+        assert_eq!((spans[0].range(), spans[0].line, spans[0].column), (0..2, 1, 1));
+        // the zero loop covers all three lines, and is reported where it opens
+        assert_eq!((spans[1].range(), spans[1].line, spans[1].column), (5..12, 2, 1));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let prog = opt("+[->++<]>[>]<[-->+<]");
+        let json = serde_json::to_string(&prog).unwrap();
+        assert!(json.starts_with(r#"[{"Add":1},{"MultiplicationLoop":[1,[[1,2]]]}"#));
+        assert_eq!(serde_json::from_str::<Vec<Stmt>>(&json).unwrap(), prog);
+    }
+
+    #[test]
+    fn modular_inverse() {
+        assert_eq!(inverse_mod_256(3), 171);
+        for d in (1..=255u8).step_by(2) {
+            assert_eq!(d.wrapping_mul(inverse_mod_256(d)), 1);
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! A minimal language server for editors, speaking the Language Server
+//! Protocol over stdio.
+//!
+//! Open documents are checked like `check` does on every change. Hovering
+//! inside a loop shows what the optimizer turned it into, the outline lists
+//! the top-level loops, and "go to definition" on a bracket jumps to the
+//! matching one.
+
+use std::collections::HashMap;
+use std::io;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics};
+use lsp_types::request::{DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as _};
+use lsp_types::{
+    DiagnosticSeverity, DocumentSymbol, DocumentSymbolResponse, GotoDefinitionResponse, Hover, HoverContents, HoverProviderCapability,
+    Location, MarkupContent, MarkupKind, NumberOrString, OneOf, Position, PublishDiagnosticsParams, Range, ServerCapabilities,
+    SymbolKind, TextDocumentSyncCapability, TextDocumentSyncKind, Uri,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::bf2c::{parse_spanned, parse_with_spans, BfSymbol, ParseOptions};
+use super::check::{check, Severity};
+use super::error::Bf2cError;
+use super::localop::{optimize_with_spans, Stmt};
+use super::profile::snippet;
+use super::repl::write_ir;
+use super::report::{collect_loops, decision};
+
+/// Serves one client on stdin and stdout until it shuts the server down.
+pub fn stdio(options: ParseOptions) -> Result<(), Bf2cError> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    let capabilities = serde_json::to_value(capabilities).expect("capabilities serialize");
+    connection.initialize(capabilities).map_err(protocol_error)?;
+    let mut server = Server::new(options);
+    for message in &connection.receiver {
+        if let Message::Request(request) = &message {
+            if connection.handle_shutdown(request).map_err(protocol_error)? {
+                break;
+            }
+        }
+        for reply in server.handle(message) {
+            connection.sender.send(reply).map_err(|e| Bf2cError::io("write to the client", io::Error::other(e.to_string())))?;
+        }
+    }
+    drop(connection);
+    io_threads.join().map_err(|e| Bf2cError::io("talk to the client", e))
+}
+
+fn protocol_error(e: lsp_server::ProtocolError) -> Bf2cError {
+    Bf2cError::Invalid(format!("language server protocol error: {}", e))
+}
+
+/// The state of the server: the text of every open document.
+pub struct Server {
+    options: ParseOptions,
+    documents: HashMap<Uri, String>,
+}
+
+impl Server {
+    pub fn new(options: ParseOptions) -> Self {
+        Server { options, documents: HashMap::new() }
+    }
+
+    /// Handles a message from the client, returning the messages to send
+    /// back. Shutdown is left to `Connection::handle_shutdown`.
+    pub fn handle(&mut self, message: Message) -> Vec<Message> {
+        match message {
+            Message::Request(request) => vec![Message::Response(self.respond(request))],
+            Message::Notification(notification) => self.notify(notification).into_iter().map(Message::Notification).collect(),
+            Message::Response(_) => Vec::new(),
+        }
+    }
+
+    fn respond(&self, request: Request) -> Response {
+        let result = match request.method.as_str() {
+            HoverRequest::METHOD => params(request.params).map(|params: lsp_types::HoverParams| {
+                let at = params.text_document_position_params;
+                self.document(&at.text_document.uri).and_then(|text| self.hover(text, at.position))
+            }).and_then(to_value),
+            GotoDefinition::METHOD => params(request.params).map(|params: lsp_types::GotoDefinitionParams| {
+                let at = params.text_document_position_params;
+                let uri = at.text_document.uri;
+                self.document(&uri).and_then(|text| {
+                    let range = self.matching_bracket(text, at.position)?;
+                    Some(GotoDefinitionResponse::Scalar(Location { uri: uri.clone(), range }))
+                })
+            }).and_then(to_value),
+            DocumentSymbolRequest::METHOD => params(request.params).map(|params: lsp_types::DocumentSymbolParams| {
+                self.document(&params.text_document.uri).map(|text| DocumentSymbolResponse::Nested(self.symbols(text)))
+            }).and_then(to_value),
+            method => Err((ErrorCode::MethodNotFound, format!("unsupported request '{}'", method))),
+        };
+        match result {
+            Ok(value) => Response::new_ok(request.id, value),
+            Err((code, message)) => Response::new_err(request.id, code as i32, message),
+        }
+    }
+
+    /// Tracks the open documents, returning the diagnostics to publish.
+    fn notify(&mut self, notification: Notification) -> Option<Notification> {
+        let uri = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams = params(notification.params).ok()?;
+                self.documents.insert(params.text_document.uri.clone(), params.text_document.text);
+                params.text_document.uri
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams = params(notification.params).ok()?;
+                // with full sync the last change holds the whole text
+                let text = params.content_changes.into_iter().last()?.text;
+                self.documents.insert(params.text_document.uri.clone(), text);
+                params.text_document.uri
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams = params(notification.params).ok()?;
+                self.documents.remove(&params.text_document.uri);
+                params.text_document.uri
+            }
+            _ => return None,
+        };
+        let diagnostics = self.documents.get(&uri).map_or_else(Vec::new, |text| self.diagnostics(text));
+        let params = PublishDiagnosticsParams { uri, diagnostics, version: None };
+        Some(Notification::new(PublishDiagnostics::METHOD.to_string(), params))
+    }
+
+    fn document(&self, uri: &Uri) -> Option<&str> {
+        self.documents.get(uri).map(String::as_str)
+    }
+
+    /// The findings of `check`, notes appended to the message.
+    fn diagnostics(&self, text: &str) -> Vec<lsp_types::Diagnostic> {
+        check(text, &self.options)
+            .iter()
+            .map(|diagnostic| {
+                let mut message = diagnostic.message.clone();
+                for note in &diagnostic.notes {
+                    message += "\n";
+                    message += note;
+                }
+                lsp_types::Diagnostic {
+                    range: diagnostic.primary_span.map_or_else(Range::default, |span| range(text, span.start, span.end)),
+                    severity: Some(match diagnostic.severity {
+                        Severity::Error => DiagnosticSeverity::ERROR,
+                        Severity::Warning => DiagnosticSeverity::WARNING,
+                    }),
+                    code: Some(NumberOrString::String(diagnostic.code.as_str().to_string())),
+                    source: Some("bf2c".to_string()),
+                    message,
+                    ..lsp_types::Diagnostic::default()
+                }
+            })
+            .collect()
+    }
+
+    /// The innermost loop around `position`, as the IR it was optimized
+    /// into and why. Needs balanced brackets, like the optimizer.
+    fn hover(&self, text: &str, position: Position) -> Option<Hover> {
+        let offset = offset(text, position);
+        let tokens = parse_spanned(text, &self.options).ok()?;
+        let (prog, spans) = optimize_with_spans(&tokens);
+        let mut loops = Vec::new();
+        collect_loops(&prog, &mut spans.iter(), &mut loops);
+        // loops are in pre-order, so the last one around the offset is innermost
+        let (stmt, span) = loops.into_iter().rfind(|(_, span)| span.range().contains(&offset))?;
+        let mut ir = String::new();
+        write_ir(&mut ir, &vec![stmt.clone()], 0);
+        let value = format!("**{}**: {}\n\n```\n{}```", title(stmt), decision(stmt), ir);
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value }),
+            range: Some(range(text, span.start, span.end)),
+        })
+    }
+
+    /// The top-level loops, named by their source.
+    fn symbols(&self, text: &str) -> Vec<DocumentSymbol> {
+        let Ok(tokens) = parse_spanned(text, &self.options) else {
+            return Vec::new();
+        };
+        let (prog, spans) = optimize_with_spans(&tokens);
+        let mut loops = Vec::new();
+        collect_loops(&prog, &mut spans.iter(), &mut loops);
+        let mut end = 0;
+        let mut symbols = Vec::new();
+        for (stmt, span) in loops {
+            if span.start < end {
+                continue;
+            }
+            end = span.end;
+            let range = range(text, span.start, span.end);
+            #[allow(deprecated)] // `deprecated` has no default
+            symbols.push(DocumentSymbol {
+                name: snippet(&text[span.range()]),
+                detail: Some(title(stmt).to_string()),
+                kind: SymbolKind::NAMESPACE,
+                tags: None,
+                deprecated: None,
+                range,
+                selection_range: range,
+                children: None,
+            });
+        }
+        symbols
+    }
+
+    /// The bracket matching the one at `position`. Works on unbalanced
+    /// documents too, for the brackets that do match.
+    fn matching_bracket(&self, text: &str, position: Position) -> Option<Range> {
+        let offset = offset(text, position);
+        let (tokens, ranges) = parse_with_spans(text, false, &self.options).expect("parsing without verification cannot fail");
+        let mut open = Vec::new();
+        for (token, close) in tokens.into_iter().zip(ranges) {
+            match token {
+                BfSymbol::OpenBracket => open.push(close),
+                BfSymbol::CloseBracket => {
+                    let Some(start) = open.pop() else {
+                        continue;
+                    };
+                    if start.contains(&offset) {
+                        return Some(range(text, close.start, close.end));
+                    }
+                    if close.contains(&offset) {
+                        return Some(range(text, start.start, start.end));
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+fn title(stmt: &Stmt) -> &'static str {
+    match stmt {
+        Stmt::ZeroLoop => "zero loop",
+        Stmt::ScanLoop(_) => "scan loop",
+        Stmt::MultiplicationLoop(..) => "multiplication loop",
+        _ => "loop",
+    }
+}
+
+type RequestError = (ErrorCode, String);
+
+fn params<P: DeserializeOwned>(params: Value) -> Result<P, RequestError> {
+    serde_json::from_value(params).map_err(|e| (ErrorCode::InvalidParams, e.to_string()))
+}
+
+fn to_value(result: impl serde::Serialize) -> Result<Value, RequestError> {
+    serde_json::to_value(result).map_err(|e| (ErrorCode::InternalError, e.to_string()))
+}
+
+/// The LSP range of bytes `start..end` of `text`.
+fn range(text: &str, start: usize, end: usize) -> Range {
+    Range::new(position(text, start), position(text, end))
+}
+
+/// Line and UTF-16 column of byte `offset` of `text`, both 0-based.
+fn position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    let line = before.matches('\n').count();
+    Position::new(line as u32, before[line_start..].encode_utf16().count() as u32)
+}
+
+/// Byte offset of `position` in `text`, clamped to the end of its line.
+fn offset(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(newline) => line_start += newline + 1,
+            None => return text.len(),
+        }
+    }
+    let mut units = 0;
+    for (index, c) in text[line_start..].char_indices() {
+        if units >= position.character || c == '\n' {
+            return line_start + index;
+        }
+        units += c.len_utf16() as u32;
+    }
+    text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_server::{Message, Notification, Request, RequestId};
+    use lsp_types::{DocumentSymbolResponse, GotoDefinitionResponse, Hover, HoverContents, Position, PublishDiagnosticsParams, Range};
+    use serde_json::{json, Value};
+
+    use super::super::bf2c::ParseOptions;
+    use super::{offset, position, Server};
+
+    fn open(server: &mut Server, text: &str) -> PublishDiagnosticsParams {
+        let params = json!({ "textDocument": { "uri": "file:///a.bf", "languageId": "brainfuck", "version": 1, "text": text } });
+        let replies = server.handle(Message::Notification(Notification::new("textDocument/didOpen".to_string(), params)));
+        match &replies[..] {
+            [Message::Notification(notification)] => serde_json::from_value(notification.params.clone()).unwrap(),
+            replies => panic!("unexpected replies {:?}", replies),
+        }
+    }
+
+    fn request(server: &mut Server, method: &str, line: u32, character: u32) -> Value {
+        let params = json!({ "textDocument": { "uri": "file:///a.bf" }, "position": { "line": line, "character": character } });
+        let replies = server.handle(Message::Request(Request::new(RequestId::from(1), method.to_string(), params)));
+        match &replies[..] {
+            [Message::Response(response)] => response.result.clone().unwrap(),
+            replies => panic!("unexpected replies {:?}", replies),
+        }
+    }
+
+    #[test]
+    fn publishes_diagnostics_and_answers_requests() {
+        let mut server = Server::new(ParseOptions::default());
+        let published = open(&mut server, "+[\n]]");
+        assert_eq!(published.diagnostics.len(), 1);
+        assert_eq!(published.diagnostics[0].range, Range::new(Position::new(1, 1), Position::new(1, 2)));
+        assert_eq!(published.diagnostics[0].code, Some(lsp_types::NumberOrString::String("unmatched-close".to_string())));
+
+        open(&mut server, "+\n[->++<]x[.[-]]");
+        let hover: Hover = serde_json::from_value(request(&mut server, "textDocument/hover", 1, 3)).unwrap();
+        let HoverContents::Markup(contents) = hover.contents else { panic!("plain hover") };
+        assert!(contents.value.starts_with("**multiplication loop**: returns to the cell it decreases by 1"), "{}", contents.value);
+        assert!(contents.value.ends_with("```\nMultiplicationLoop(1, [(1, 2)])\n```"));
+        // the innermost loop wins
+        let hover: Hover = serde_json::from_value(request(&mut server, "textDocument/hover", 1, 12)).unwrap();
+        assert_eq!(hover.range, Some(Range::new(Position::new(1, 10), Position::new(1, 13))));
+        assert_eq!(request(&mut server, "textDocument/hover", 0, 0), Value::Null);
+
+        let definition: GotoDefinitionResponse = serde_json::from_value(request(&mut server, "textDocument/definition", 1, 8)).unwrap();
+        let GotoDefinitionResponse::Scalar(location) = definition else { panic!("several definitions") };
+        assert_eq!(location.range, Range::new(Position::new(1, 13), Position::new(1, 14)));
+
+        let symbols: DocumentSymbolResponse = serde_json::from_value(request(&mut server, "textDocument/documentSymbol", 0, 0)).unwrap();
+        let DocumentSymbolResponse::Nested(symbols) = symbols else { panic!("flat symbols") };
+        let names: Vec<_> = symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(names, ["[->++<]", "[.[-]]"]);
+    }
+
+    #[test]
+    fn positions_count_utf16_units() {
+        let text = "é\n𝄞[";
+        assert_eq!(position(text, 7), Position::new(1, 2));
+        assert_eq!(offset(text, Position::new(1, 2)), 7);
+        assert_eq!(offset(text, Position::new(0, 9)), 2);
+        assert_eq!(offset(text, Position::new(5, 0)), text.len());
+    }
+}
@@ -1,8 +1,67 @@
+pub mod check;
+#[cfg(feature = "std")]
+pub mod compile;
+#[cfg(feature = "dap")]
+pub mod dap;
+#[cfg(feature = "std")]
+pub mod debugger;
+pub mod diagnostic;
+pub mod dialect;
+pub mod dot;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod heatmap;
+#[cfg(feature = "std")]
+pub mod include;
+#[cfg(feature = "std")]
+pub mod interp;
+#[cfg(feature = "jit")]
+pub mod jit;
+#[cfg(feature = "std")]
+pub mod journal;
+pub mod literate;
+pub mod localop;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod repl;
+#[cfg(feature = "std")]
+pub mod report;
+#[cfg(feature = "std")]
+pub mod snapshot;
+mod stopwatch;
+#[cfg(feature = "std")]
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod trace;
+pub mod transpiler;
+#[cfg(feature = "std")]
+pub mod verify;
+#[cfg(feature = "std")]
+pub mod watch;
+
+#[allow(clippy::module_inception)]
 pub mod bf2c {
-    use indoc::indoc;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use core::ops::Range;
+    use indoc::{formatdoc, indoc};
+    use tracing::{field, info, info_span};
+
+    use super::check::line_column;
+    use super::dialect::Dialect;
+    use super::literate::code_only;
+    use super::stopwatch::Stopwatch;
+    use super::transpiler::{CellWidth, Target};
 
     #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-    enum BfSymbol {
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub enum BfSymbol {
         Left,
         Right,
         Plus,
@@ -11,52 +70,241 @@ pub mod bf2c {
         Comma,
         OpenBracket,
         CloseBracket,
+        /// `#`, dumps the tape around the pointer. Only produced when
+        /// `ParseOptions::debug` is set.
+        Debug,
+    }
+
+    /// Extensions to the plain eight-instruction language.
+    #[derive(Debug, Clone, Default)]
+    pub struct ParseOptions {
+        /// Keep `#` as `BfSymbol::Debug` instead of treating it as a comment.
+        /// Only applies to `Dialect::Brainfuck`.
+        pub debug: bool,
+        /// How the instructions are spelled.
+        pub dialect: Dialect,
+        /// Only read code from fenced blocks and `>` quoted lines, see
+        /// `literate`.
+        pub literate: bool,
+    }
+
+    /// Unmatched bracket found by a verifying parse. Lines and columns are
+    /// 1-based, columns count characters.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ParseError {
+        /// A `]` without a matching `[`.
+        UnmatchedClose { offset: usize, line: usize, column: usize },
+        /// A `[` that is still open at the end of the source, the innermost
+        /// one if there are several.
+        UnclosedOpen { offset: usize, line: usize, column: usize },
     }
-    fn parse_without_verification(buf: &str) -> Vec<BfSymbol> {
+
+    impl ParseError {
+        /// Byte offset of the offending bracket.
+        pub fn offset(&self) -> usize {
+            match *self {
+                ParseError::UnmatchedClose { offset, .. } | ParseError::UnclosedOpen { offset, .. } => offset,
+            }
+        }
+
+        /// The same error at `offset` in `source`, for errors found in text
+        /// assembled from several files.
+        pub fn relocate(&self, source: &str, offset: usize) -> ParseError {
+            let (line, column) = line_column(source, offset);
+            match self {
+                ParseError::UnmatchedClose { .. } => ParseError::UnmatchedClose { offset, line, column },
+                ParseError::UnclosedOpen { .. } => ParseError::UnclosedOpen { offset, line, column },
+            }
+        }
+    }
+
+    impl core::fmt::Display for ParseError {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            match self {
+                ParseError::UnmatchedClose { line, column, .. } => {
+                    write!(f, "missing open bracket for ']' at line {}, column {}", line, column)
+                }
+                ParseError::UnclosedOpen { line, column, .. } => {
+                    write!(f, "'[' opened at line {}, column {} is never closed", line, column)
+                }
+            }
+        }
+    }
+
+    impl core::error::Error for ParseError {}
+
+    /// Where a token or IR statement comes from: the byte range `start..end`
+    /// of the source, and the 1-based line and column of `start`. Columns
+    /// count characters.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct Span {
+        pub start: usize,
+        pub end: usize,
+        pub line: usize,
+        pub column: usize,
+    }
+
+    impl Span {
+        pub fn range(&self) -> Range<usize> {
+            self.start..self.end
+        }
+
+        /// Span from the start of `self` to the end of `last`.
+        pub fn to(&self, last: &Span) -> Span {
+            Span { end: last.end, ..*self }
+        }
+    }
+
+    impl From<ParseError> for String {
+        fn from(e: ParseError) -> String {
+            e.to_string()
+        }
+    }
+
+    pub fn parse_without_verification(buf: &str) -> Vec<BfSymbol> {
         parse(buf, false).unwrap()
     }
-    fn parse(buf: &str, verify: bool) -> Result<Vec<BfSymbol>, &'static str> {
-        let mut out = Vec::new();
-        let mut bracket_depth = 0;
-        for c in buf.trim().chars() {
-            match c {
-                '<' => out.push(BfSymbol::Left),
-                '>' => out.push(BfSymbol::Right),
-                '+' => out.push(BfSymbol::Plus),
-                '-' => out.push(BfSymbol::Minus),
-                '.' => out.push(BfSymbol::Period),
-                ',' => out.push(BfSymbol::Comma),
-                '[' => {out.push(BfSymbol::OpenBracket);
-                    if verify {
-                        bracket_depth += 1;
-                    }
-                },
-                ']' => {out.push(BfSymbol::CloseBracket);
-                    if verify {
-                        if bracket_depth == 0 {
-                            return Err("missing open bracket");
-                        }
-                        bracket_depth -= 1;
+    pub fn parse(buf: &str, verify: bool) -> Result<Vec<BfSymbol>, ParseError> {
+        parse_with_offsets(buf, verify).map(|(tokens, _)| tokens)
+    }
+
+    /// Like `parse`, but also returns the byte offset in `buf` of every token.
+    pub fn parse_with_offsets(buf: &str, verify: bool) -> Result<(Vec<BfSymbol>, Vec<usize>), ParseError> {
+        parse_with_options(buf, verify, &ParseOptions::default())
+    }
+
+    /// Like `parse_with_offsets`, with language extensions enabled by `options`.
+    pub fn parse_with_options(
+        buf: &str,
+        verify: bool,
+        options: &ParseOptions,
+    ) -> Result<(Vec<BfSymbol>, Vec<usize>), ParseError> {
+        let (tokens, spans) = parse_with_spans(buf, verify, options)?;
+        Ok((tokens, spans.iter().map(|span| span.start).collect()))
+    }
+
+    /// Like `parse_with_options`, but returns the byte range of every token,
+    /// which is longer than one byte in some dialects.
+    pub fn parse_with_spans(
+        buf: &str,
+        verify: bool,
+        options: &ParseOptions,
+    ) -> Result<(Vec<BfSymbol>, Vec<Range<usize>>), ParseError> {
+        let span = info_span!("parse", bytes = buf.len(), tokens = field::Empty).entered();
+        let start = Stopwatch::start();
+        let code;
+        let text = if options.literate {
+            code = code_only(buf);
+            &code
+        } else {
+            buf
+        };
+        let (out, spans) = match &options.dialect {
+            Dialect::Brainfuck => lex(text, options.debug),
+            dialect => dialect.lex(text),
+        };
+        if verify {
+            verify_brackets(buf, &out, &spans)?;
+        }
+        span.record("tokens", out.len());
+        info!(bytes = buf.len(), tokens = out.len(), elapsed = ?start.elapsed(), "parsed");
+        Ok((out, spans))
+    }
+
+    /// Verified tokens of `buf`, each with its span.
+    pub fn parse_spanned(buf: &str, options: &ParseOptions) -> Result<Vec<(BfSymbol, Span)>, ParseError> {
+        let (tokens, ranges) = parse_with_spans(buf, true, options)?;
+        Ok(tokens.into_iter().zip(locate(buf, ranges)).collect())
+    }
+
+    /// Adds lines and columns to `ranges`, which must be in source order.
+    fn locate(buf: &str, ranges: Vec<Range<usize>>) -> Vec<Span> {
+        let (mut offset, mut line, mut column) = (0, 1, 1);
+        ranges
+            .into_iter()
+            .map(|range| {
+                for c in buf[offset..range.start].chars() {
+                    if c == '\n' {
+                        line += 1;
+                        column = 1;
+                    } else {
+                        column += 1;
                     }
-                },
-                _ => {} // ignore non-BF characters
+                }
+                offset = range.start;
+                Span { start: range.start, end: range.end, line, column }
+            })
+            .collect()
+    }
+
+    fn lex(buf: &str, debug: bool) -> (Vec<BfSymbol>, Vec<Range<usize>>) {
+        buf.char_indices()
+            .filter_map(|(offset, c)| {
+                let symbol = match c {
+                    '<' => BfSymbol::Left,
+                    '>' => BfSymbol::Right,
+                    '+' => BfSymbol::Plus,
+                    '-' => BfSymbol::Minus,
+                    '.' => BfSymbol::Period,
+                    ',' => BfSymbol::Comma,
+                    '[' => BfSymbol::OpenBracket,
+                    ']' => BfSymbol::CloseBracket,
+                    '#' if debug => BfSymbol::Debug,
+                    _ => return None, // ignore non-BF characters
+                };
+                Some((symbol, offset..offset + 1))
+            })
+            .unzip()
+    }
+
+    fn verify_brackets(buf: &str, tokens: &[BfSymbol], spans: &[Range<usize>]) -> Result<(), ParseError> {
+        let mut open_brackets = Vec::new();
+        for (token, span) in tokens.iter().zip(spans) {
+            match token {
+                BfSymbol::OpenBracket => open_brackets.push(span.start),
+                BfSymbol::CloseBracket if open_brackets.pop().is_none() => {
+                    let (line, column) = line_column(buf, span.start);
+                    return Err(ParseError::UnmatchedClose { offset: span.start, line, column });
+                }
+                _ => {}
             }
         }
-        if bracket_depth != 0 {
-            return Err("Brainfuck code is not well-formed (Brackets do not match)");
+        match open_brackets.last() {
+            Some(&offset) => {
+                let (line, column) = line_column(buf, offset);
+                Err(ParseError::UnclosedOpen { offset, line, column })
+            }
+            None => Ok(()),
         }
-        Ok(out)
     }
 
-    fn wrap_boilerplate(code: String) -> String {
-        let boilerplate = String::from(indoc! {
-            "#include <stdio.h>
-             int main() {
-                char tape[200000];
-                for (int i = 0; i < 200000; i++) tape[i] = 0;
-                char *ptr = tape;
-            "
-        });
+    /// C for `#`: prints the cells around the pointer to stderr, in the same
+    /// format as the interpreter.
+    pub(crate) fn debug_dump(target: &Target) -> String {
+        format!(
+            "{{ long p = ptr - tape; fprintf(stderr, \"ptr=%ld:\", p); \
+            for (long i = p < 4 ? 0 : p - 4; i < p + 5 && i < {}; i++) \
+            fprintf(stderr, i == p ? \" [%lu]\" : \" %lu\", (unsigned long){}tape[i]); fputc('\\n', stderr); }}",
+            target.tape_size,
+            // plain char may be signed
+            if target.cell_width == CellWidth::U8 { "(unsigned char)" } else { "" }
+        )
+    }
+
+    /// `code` inside `main`, after setting up a zeroed tape.
+    pub(crate) fn wrap_boilerplate(code: String, target: &Target) -> String {
+        let boilerplate = formatdoc! {
+            "{stdint}#include <stdio.h>
+             int main() {{
+                {cell} tape[{size}];
+                for (int i = 0; i < {size}; i++) tape[i] = 0;
+                {cell} *ptr = tape;
+            ",
+            stdint = if target.cell_width == CellWidth::U8 { "" } else { "#include <stdint.h>\n" },
+            cell = target.cell_width.c_type(),
+            size = target.tape_size,
+        };
 
         let boilerplate_end = String::from(indoc! {
             "   return 0;
@@ -66,12 +314,15 @@ pub mod bf2c {
         format!("{}{}{}", boilerplate, code, boilerplate_end)
     }
 
-    fn emit(tokens: &Vec<BfSymbol>) -> String {
-        wrap_boilerplate(emit_without_boilerplate(tokens))
+    /// One statement per instruction, for the default `Target`.
+    #[cfg(any(test, feature = "std"))]
+    pub(crate) fn emit_without_boilerplate(tokens: &[BfSymbol]) -> String {
+        emit_tokens(tokens, &Target::default())
     }
 
-    fn emit_without_boilerplate(tokens: &Vec<BfSymbol>) -> String {
-        use std::fmt::Write;
+    /// One statement per instruction.
+    pub(crate) fn emit_tokens(tokens: &[BfSymbol], target: &Target) -> String {
+        use core::fmt::Write;
         let mut out = String::new();
         let indent = " ".repeat(4);
         let mut indent_depth = 1; // core code is inside int main()
@@ -79,10 +330,10 @@ pub mod bf2c {
         for token in tokens {
             match token {
                 BfSymbol::Left => {
-                    writeln!(&mut out, "{}ptr++;", indent.repeat(indent_depth), ).unwrap();
+                    writeln!(&mut out, "{}ptr--;", indent.repeat(indent_depth), ).unwrap();
                 }
                 BfSymbol::Right => {
-                    writeln!(&mut out, "{}ptr--;", indent.repeat(indent_depth), ).unwrap();
+                    writeln!(&mut out, "{}ptr++;", indent.repeat(indent_depth), ).unwrap();
                 }
                 BfSymbol::Plus => {
                     writeln!(&mut out, "{}(*ptr)++;", indent.repeat(indent_depth)).unwrap();
@@ -104,21 +355,22 @@ pub mod bf2c {
                     indent_depth -= 1;
                     writeln!(&mut out, "{}}}", indent.repeat(indent_depth)).unwrap();
                 }
+                BfSymbol::Debug => {
+                    writeln!(&mut out, "{}{}", indent.repeat(indent_depth), debug_dump(target)).unwrap();
+                }
             }
         }
         out
     }
 
-    pub fn bf2cify(input: String) -> Result<String, String>{
-        let parsed = parse(input.as_str(), true)?;
-        Ok(emit(&parsed))
-    }
-
-
     #[cfg(test)]
     mod tests {
+        use alloc::string::{String, ToString};
+        use alloc::vec;
+        use alloc::vec::Vec;
         use indoc::indoc;
-        use super::{BfSymbol, parse_without_verification, parse, emit, emit_without_boilerplate};
+        use super::{BfSymbol, ParseError, ParseOptions, Span, parse_without_verification, parse, parse_with_offsets, parse_with_options, parse_spanned, emit_without_boilerplate};
+        use super::super::transpiler::Transpiler;
         #[test]
         fn parse_empty() {
             assert!(parse_without_verification("").is_empty());
@@ -146,6 +398,29 @@ pub mod bf2c {
             assert_eq!(tokens[1], BfSymbol::CloseBracket);
         }
 
+        #[test]
+        fn parse_offsets() {
+            let (tokens, offsets) = parse_with_offsets(" +a\n[é]", true).unwrap();
+            assert_eq!(tokens, vec![BfSymbol::Plus, BfSymbol::OpenBracket, BfSymbol::CloseBracket]);
+            assert_eq!(offsets, vec![1, 4, 7]);
+        }
+
+        #[test]
+        fn parse_debug_symbol() {
+            assert!(parse_without_verification("#").is_empty());
+            let options = ParseOptions { debug: true, ..ParseOptions::default() };
+            let (tokens, offsets) = parse_with_options("+ #", true, &options).unwrap();
+            assert_eq!(tokens, vec![BfSymbol::Plus, BfSymbol::Debug]);
+            assert_eq!(offsets, vec![0, 2]);
+        }
+
+        #[test]
+        fn emit_debug_symbol() {
+            let out = emit_without_boilerplate(&[BfSymbol::Debug]);
+            assert!(out.starts_with("    { long p = ptr - tape; fprintf(stderr, \"ptr=%ld:\", p);"));
+            assert!(out.ends_with("fputc('\\n', stderr); }\n"));
+        }
+
         #[test]
         fn parse_missing_open_bracket() {
             let tokens = parse("]", true);
@@ -158,9 +433,29 @@ pub mod bf2c {
             assert!(tokens.is_err())
         }
 
+        #[test]
+        fn parse_error_positions() {
+            assert_eq!(
+                parse("+\n é]", true),
+                Err(ParseError::UnmatchedClose { offset: 5, line: 2, column: 3 })
+            );
+            // the innermost bracket is reported
+            let e = parse("[\n[[]", true).unwrap_err();
+            assert_eq!(e, ParseError::UnclosedOpen { offset: 2, line: 2, column: 1 });
+            assert_eq!(e.to_string(), "'[' opened at line 2, column 1 is never closed");
+        }
+
+        #[test]
+        fn spanned_tokens() {
+            let tokens = parse_spanned("+ x\n é[-]", &ParseOptions::default()).unwrap();
+            assert_eq!(tokens[0], (BfSymbol::Plus, Span { start: 0, end: 1, line: 1, column: 1 }));
+            assert_eq!(tokens[1], (BfSymbol::OpenBracket, Span { start: 7, end: 8, line: 2, column: 3 }));
+            assert_eq!(tokens[3].1.column, 5);
+            assert!(parse_spanned("]", &ParseOptions::default()).is_err());
+        }
+
         #[test]
         fn emit_empty_program() {
-            let tokens: Vec<BfSymbol> = vec![];
             let expected= indoc ! {
                 "#include <stdio.h>
                  int main() {
@@ -171,7 +466,7 @@ pub mod bf2c {
                  }
                  "
                 };
-            assert_eq!(emit(&tokens), expected);
+            assert_eq!(Transpiler::default().transpile("").unwrap(), expected);
         }
 
         fn trim_leading_spaces(s: String) -> String {
@@ -191,8 +486,8 @@ pub mod bf2c {
                 BfSymbol::CloseBracket,
             ];
             let expected = indoc! {"
-                 ptr++;
                  ptr--;
+                 ptr++;
                  (*ptr)++;
                  (*ptr)--;
                  putchar(*ptr);
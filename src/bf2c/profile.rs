@@ -0,0 +1,145 @@
+//! Execution profiler reporting which loops and statements dominate the
+//! runtime of a program.
+
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::ops::Range;
+
+use super::bf2c::{parse_with_spans, ParseOptions};
+use super::error::Bf2cError;
+use super::interp::{Limits, Machine};
+use super::localop::optimize_with_ranges;
+
+/// Longest source excerpt shown in a report line.
+const SNIPPET_LEN: usize = 40;
+
+pub struct LoopProfile {
+    pub span: Range<usize>,
+    pub iterations: u64,
+    /// Steps executed inside the loop, including nested loops.
+    pub steps: u64,
+}
+
+pub struct StmtProfile {
+    pub span: Range<usize>,
+    pub executions: u64,
+}
+
+pub struct Profile {
+    /// Loops ranked by the number of steps executed inside them.
+    pub loops: Vec<LoopProfile>,
+    /// IR statements ranked by the number of times they were executed.
+    pub statements: Vec<StmtProfile>,
+    pub steps: u64,
+}
+
+/// Runs `source` over the optimized IR, counting loop iterations and
+/// executions of every statement.
+pub fn profile<R: Read, W: Write>(
+    source: &str,
+    input: &mut R,
+    output: &mut W,
+    limits: &Limits,
+    options: &ParseOptions,
+) -> Result<Profile, Bf2cError> {
+    let (tokens, spans) = parse_with_spans(source, true, options)?;
+    let (prog, ranges) = optimize_with_ranges(&tokens);
+    let mut machine = Machine::from_prog(&prog, &ranges, &spans);
+    machine.run(input, output, limits)?;
+
+    let hits = machine.hits();
+    let mut loops: Vec<LoopProfile> = machine
+        .loops()
+        .into_iter()
+        .filter(|&(_, end)| hits[end] > 0)
+        .map(|(start, end)| LoopProfile {
+            span: machine.span_at(start).start..machine.span_at(end).end,
+            iterations: hits[end],
+            steps: hits[start..=end].iter().sum(),
+        })
+        .collect();
+    loops.sort_by(|a, b| b.steps.cmp(&a.steps).then(a.span.start.cmp(&b.span.start)));
+
+    let mut statements: Vec<StmtProfile> = (0..hits.len())
+        .filter(|&pc| hits[pc] > 0 && !machine.is_loop_boundary(pc))
+        .map(|pc| StmtProfile { span: machine.span_at(pc), executions: hits[pc] })
+        .collect();
+    statements.sort_by(|a, b| b.executions.cmp(&a.executions).then(a.span.start.cmp(&b.span.start)));
+
+    Ok(Profile { loops, statements, steps: machine.steps })
+}
+
+impl Profile {
+    /// Renders the `limit` hottest loops and statements.
+    pub fn report(&self, source: &str, limit: usize) -> String {
+        let mut out = String::new();
+        writeln!(out, "executed {} steps", self.steps).unwrap();
+        writeln!(out, "\nhot loops:").unwrap();
+        writeln!(out, "{:>8}  {:>12}  {:>12}  source", "offset", "iterations", "steps").unwrap();
+        for entry in self.loops.iter().take(limit) {
+            writeln!(
+                out,
+                "{:>8}  {:>12}  {:>12}  {}",
+                entry.span.start,
+                entry.iterations,
+                entry.steps,
+                snippet(&source[entry.span.clone()])
+            )
+            .unwrap();
+        }
+        writeln!(out, "\nhot statements:").unwrap();
+        writeln!(out, "{:>8}  {:>12}  source", "offset", "executions").unwrap();
+        for entry in self.statements.iter().take(limit) {
+            writeln!(
+                out,
+                "{:>8}  {:>12}  {}",
+                entry.span.start,
+                entry.executions,
+                snippet(&source[entry.span.clone()])
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+/// Source excerpt on a single line, shortened to `SNIPPET_LEN` characters.
+pub(crate) fn snippet(text: &str) -> String {
+    let flat: String = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if flat.chars().count() <= SNIPPET_LEN {
+        return flat;
+    }
+    let short: String = flat.chars().take(SNIPPET_LEN - 3).collect();
+    format!("{}...", short)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::interp::Limits;
+    use super::{profile, ParseOptions};
+
+    #[test]
+    fn ranks_hot_loops() {
+        // the outer loop runs 3 times, the inner one 3 * 4 times
+        let source = "+++[>++++[>+<-.]<-]";
+        let result = profile(source, &mut &b""[..], &mut Vec::new(), &Limits::default(), &ParseOptions::default()).unwrap();
+        assert_eq!(result.loops.len(), 2);
+        assert_eq!(result.loops[0].span, 3..19);
+        assert_eq!(result.loops[0].iterations, 3);
+        assert_eq!(result.loops[1].span, 9..16);
+        assert_eq!(result.loops[1].iterations, 12);
+        assert!(result.loops[0].steps > result.loops[1].steps);
+        assert_eq!(result.statements[0].executions, 12);
+    }
+
+    #[test]
+    fn report_lists_offsets() {
+        let source = "++[-]\n[>+<-.]";
+        let result = profile(source, &mut &b""[..], &mut Vec::new(), &Limits::default(), &ParseOptions::default()).unwrap();
+        let report = result.report(source, 10);
+        assert!(report.starts_with("executed 3 steps\n"));
+        assert!(report.contains("       0             1  ++"));
+        // the second loop is never entered
+        assert!(!report.contains("[>+<-.]"));
+    }
+}
@@ -0,0 +1,97 @@
+//! Read-eval-print loop for Brainfuck snippets: every snippet is parsed,
+//! optimized and transpiled, and the result of each stage is shown.
+
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+
+use super::bf2c::{emit_without_boilerplate, parse_with_options, ParseError, ParseOptions};
+use super::error::Bf2cError;
+use super::localop::{optimize, Prog, Stmt};
+
+const HELP: &str = "\
+Type a Brainfuck snippet to see its tokens, optimized IR and generated C.
+A snippet with unclosed brackets continues on the next line.
+commands:
+  :help   (:h)  show this message
+  :quit   (:q)  leave
+";
+
+/// Reads snippets from `input` until end of input or `:quit`.
+pub fn repl<I: BufRead, W: Write>(input: &mut I, out: &mut W, options: &ParseOptions) -> Result<(), Bf2cError> {
+    let mut snippet = String::new();
+    loop {
+        let prompt = if snippet.is_empty() { "(bf) " } else { "...  " };
+        write!(out, "{}", prompt).and_then(|_| out.flush()).map_err(|e| Bf2cError::io("write output", e))?;
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(|e| Bf2cError::io("read input", e))? == 0 {
+            return Ok(());
+        }
+        let report = match line.trim() {
+            ":quit" | ":q" => return Ok(()),
+            ":help" | ":h" => HELP.to_string(),
+            "" if snippet.is_empty() => continue,
+            _ => {
+                snippet += &line;
+                match explain(&snippet, options) {
+                    // wait for the rest of the loop
+                    Err(ParseError::UnclosedOpen { .. }) => continue,
+                    Err(e) => format!("error: {}\n", e),
+                    Ok(report) => report,
+                }
+            }
+        };
+        snippet.clear();
+        write!(out, "{}", report).map_err(|e| Bf2cError::io("write output", e))?;
+    }
+}
+
+/// Token count, IR and C code of `snippet`.
+pub fn explain(snippet: &str, options: &ParseOptions) -> Result<String, ParseError> {
+    let (tokens, _) = parse_with_options(snippet, true, options)?;
+    let mut out = String::new();
+    writeln!(out, "tokens: {}", tokens.len()).unwrap();
+    out += "ir:\n";
+    write_ir(&mut out, &optimize(&tokens), 1);
+    out += "c:\n";
+    out += &emit_without_boilerplate(&tokens);
+    Ok(out)
+}
+
+/// One statement per line, loop bodies indented below their loop.
+pub(crate) fn write_ir(out: &mut String, prog: &Prog, depth: usize) {
+    for stmt in prog {
+        let indent = "    ".repeat(depth);
+        match stmt {
+            Stmt::Loop(body) => {
+                writeln!(out, "{}Loop", indent).unwrap();
+                write_ir(out, body, depth + 1);
+            }
+            _ => writeln!(out, "{}{:?}", indent, stmt).unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bf2c::ParseOptions;
+    use super::{explain, repl};
+
+    #[test]
+    fn explains_each_stage() {
+        let report = explain("++[>+<-.]", &ParseOptions::default()).unwrap();
+        assert_eq!(
+            report,
+            "tokens: 9\nir:\n    Add(2)\n    Loop\n        Move(1)\n        Add(1)\n        Move(-1)\n        Add(-1)\n        Output(1)\n\
+             c:\n    (*ptr)++;\n    (*ptr)++;\n    while (*ptr) {\n        ptr++;\n        (*ptr)++;\n        ptr--;\n        (*ptr)--;\n        putchar(*ptr);\n    }\n"
+        );
+    }
+
+    #[test]
+    fn unclosed_loops_continue_on_the_next_line() {
+        let mut out = Vec::new();
+        repl(&mut &b"+[\n-]\n]\n:q\n"[..], &mut out, &ParseOptions::default()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("(bf) ...  tokens: 4\n"), "{}", out);
+        assert!(out.contains("(bf) error: missing open bracket for ']' at line 1, column 1\n(bf) "), "{}", out);
+    }
+}
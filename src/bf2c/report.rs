@@ -0,0 +1,241 @@
+//! Standalone HTML page showing what the optimizer made of a program: the
+//! source with every loop highlighted, why each loop was or was not
+//! simplified, the IR, and profiling data if the program was run.
+
+use std::fmt::Write as _;
+use std::slice;
+
+use super::bf2c::{parse_spanned, ParseError, ParseOptions, Span};
+use super::check::line_column;
+use super::localop::{optimize_with_spans, Prog, Stmt};
+use super::profile::{snippet, Profile};
+use super::repl::write_ir;
+
+/// Statements listed in the profiling section.
+const HOT_STATEMENTS: usize = 10;
+
+/// Analysis of `source` as an HTML page, with the counts of `profile` if
+/// the program was run.
+pub fn report(source: &str, options: &ParseOptions, profile: Option<&Profile>) -> Result<String, ParseError> {
+    let tokens = parse_spanned(source, options)?;
+    let (prog, spans) = optimize_with_spans(&tokens);
+    let mut loops = Vec::new();
+    collect_loops(&prog, &mut spans.iter(), &mut loops);
+
+    let mut out = String::new();
+    out += "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Program analysis</title>\n<style>\n";
+    out += "body { font-family: sans-serif; }\n";
+    out += "pre, td.source { font-family: monospace; }\n";
+    out += "pre { background: #f8f8f8; padding: 0.5em; }\n";
+    out += "table { border-collapse: collapse; }\n";
+    out += "td, th { border: 1px solid #ddd; padding: 0.2em 0.5em; text-align: left; vertical-align: top; }\n";
+    out += ".loop { outline: 1px solid #999; }\n.zero { background: lightblue; }\n";
+    out += ".scan { background: lightyellow; }\n.multiplication { background: palegreen; }\n";
+    out += "</style>\n</head>\n<body>\n<h1>Program analysis</h1>\n";
+    let count = |kind: &str| loops.iter().filter(|(stmt, _)| self::kind(stmt) == kind).count();
+    writeln!(
+        out,
+        "<p>{} bytes, {} instructions, {} IR statements. {} loops: {} kept, {} zero, {} scan and {} multiplication loops.</p>",
+        source.len(),
+        tokens.len(),
+        spans.len(),
+        loops.len(),
+        count("loop"),
+        count("zero"),
+        count("scan"),
+        count("multiplication")
+    )
+    .unwrap();
+
+    out += "<h2>Source</h2>\n<pre>";
+    annotate(&mut out, source, &loops);
+    out += "</pre>\n";
+
+    out += "<h2>Loops</h2>\n<table>\n<tr><th>#</th><th>line</th><th>kind</th><th>decision</th><th>source</th>";
+    if profile.is_some() {
+        out += "<th>executed</th>";
+    }
+    out += "</tr>\n";
+    for (index, (stmt, span)) in loops.iter().enumerate() {
+        write!(
+            out,
+            "<tr><td><a href=\"#loop-{}\">{}</a></td><td>{}:{}</td><td>{}</td><td>{}</td><td class=\"source\">{}</td>",
+            index,
+            index,
+            span.line,
+            span.column,
+            kind(stmt),
+            escape(&decision(stmt)),
+            escape(&snippet(&source[span.range()]))
+        )
+        .unwrap();
+        if let Some(profile) = profile {
+            write!(out, "<td>{}</td>", executed(profile, stmt, span)).unwrap();
+        }
+        out += "</tr>\n";
+    }
+    out += "</table>\n";
+
+    out += "<h2>IR</h2>\n<pre>";
+    let mut ir = String::new();
+    write_ir(&mut ir, &prog, 0);
+    out += &escape(&ir);
+    out += "</pre>\n";
+
+    if let Some(profile) = profile {
+        writeln!(out, "<h2>Profile</h2>\n<p>Executed {} steps.</p>", profile.steps).unwrap();
+        out += "<table>\n<tr><th>line</th><th>executions</th><th>source</th></tr>\n";
+        for entry in profile.statements.iter().take(HOT_STATEMENTS) {
+            let (line, column) = line_column(source, entry.span.start);
+            writeln!(
+                out,
+                "<tr><td>{}:{}</td><td>{}</td><td class=\"source\">{}</td></tr>",
+                line,
+                column,
+                entry.executions,
+                escape(&snippet(&source[entry.span.clone()]))
+            )
+            .unwrap();
+        }
+        out += "</table>\n";
+    }
+    out += "</body>\n</html>\n";
+    Ok(out)
+}
+
+/// Every loop of `prog` with its span, in source order.
+pub(crate) fn collect_loops<'a>(prog: &'a Prog, spans: &mut slice::Iter<Span>, loops: &mut Vec<(&'a Stmt, Span)>) {
+    for stmt in prog {
+        let span = *spans.next().expect("one span per statement");
+        if !kind(stmt).is_empty() {
+            loops.push((stmt, span));
+        }
+        if let Stmt::Loop(body) = stmt {
+            collect_loops(body, spans, loops);
+        }
+    }
+}
+
+/// CSS class of a loop, empty for other statements.
+fn kind(stmt: &Stmt) -> &'static str {
+    match stmt {
+        Stmt::Loop(_) => "loop",
+        Stmt::ZeroLoop => "zero",
+        Stmt::ScanLoop(_) => "scan",
+        Stmt::MultiplicationLoop(..) => "multiplication",
+        _ => "",
+    }
+}
+
+/// Why the optimizer turned a loop into `stmt`.
+pub(crate) fn decision(stmt: &Stmt) -> String {
+    match stmt {
+        Stmt::ZeroLoop => "only adds an odd amount to the current cell, so it sets it to zero".into(),
+        Stmt::ScanLoop(direction) => format!("only moves, by {}, so it scans for a zero cell", direction),
+        Stmt::MultiplicationLoop(decrement, effects) => {
+            let mut text = format!("returns to the cell it decreases by {} per iteration, replaced by", decrement);
+            for (index, (offset, factor)) in effects.iter().enumerate() {
+                let separator = if index == 0 { "" } else { "," };
+                write!(text, "{} cell {:+} += {} * n", separator, offset, factor).unwrap();
+            }
+            if effects.is_empty() {
+                text += " clearing the cell";
+            }
+            text
+        }
+        Stmt::Loop(body) => kept(body),
+        _ => String::new(),
+    }
+}
+
+/// Why a loop with `body` could not be simplified.
+fn kept(body: &Prog) -> String {
+    let (mut offset, mut control) = (0, 0);
+    for stmt in body {
+        match stmt {
+            Stmt::Move(distance) => offset += distance,
+            Stmt::Add(delta) if offset == 0 => control += delta,
+            Stmt::Add(_) => {}
+            Stmt::Output(_) | Stmt::Input(_) => return "kept, its body does I/O".into(),
+            Stmt::Debug => return "kept, its body dumps the tape".into(),
+            _ => return "kept, its body contains a loop".into(),
+        }
+    }
+    if offset != 0 {
+        return format!("kept, the pointer moves by {} per iteration", offset);
+    }
+    format!("kept, the current cell changes by {} per iteration, which may never reach zero", control)
+}
+
+/// What `profile` counted for the loop `stmt` at `span`.
+fn executed(profile: &Profile, stmt: &Stmt, span: &Span) -> String {
+    if let Stmt::Loop(_) = stmt {
+        // loops that were never entered are not listed
+        return match profile.loops.iter().find(|entry| entry.span == span.range()) {
+            Some(entry) => format!("{} iterations, {} steps", entry.iterations, entry.steps),
+            None => "never entered".into(),
+        };
+    }
+    let times = profile.statements.iter().find(|entry| entry.span == span.range()).map_or(0, |entry| entry.executions);
+    format!("{} times", times)
+}
+
+/// `source` with every loop in `loops` wrapped in a span of its kind.
+fn annotate(out: &mut String, source: &str, loops: &[(&Stmt, Span)]) {
+    let mut copied = 0;
+    let mut open: Vec<usize> = Vec::new();
+    for (index, (stmt, span)) in loops.iter().enumerate() {
+        while let Some(&end) = open.last().filter(|&&end| end <= span.start) {
+            *out += &escape(&source[copied..end]);
+            *out += "</span>";
+            copied = end;
+            open.pop();
+        }
+        *out += &escape(&source[copied..span.start]);
+        write!(out, "<span class=\"{}\" id=\"loop-{}\" title=\"{}\">", kind(stmt), index, escape(&decision(stmt))).unwrap();
+        copied = span.start;
+        open.push(span.end);
+    }
+    while let Some(end) = open.pop() {
+        *out += &escape(&source[copied..end]);
+        *out += "</span>";
+        copied = end;
+    }
+    *out += &escape(&source[copied..]);
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bf2c::ParseOptions;
+    use super::super::interp::Limits;
+    use super::super::profile::profile;
+    use super::report;
+
+    #[test]
+    fn explains_every_loop() {
+        let source = "+[->+<]\n,[.[-]>]";
+        let html = report(source, &ParseOptions::default(), None).unwrap();
+        assert!(html.contains("<p>16 bytes, 15 instructions, 7 IR statements. 3 loops: 1 kept, 1 zero, 0 scan and 1 multiplication loops.</p>"));
+        assert!(html.contains(concat!(
+            "<pre>+<span class=\"multiplication\" id=\"loop-0\" title=\"returns to the cell it decreases by 1 per iteration, ",
+            "replaced by cell +1 += 1 * n\">[-&gt;+&lt;]</span>\n,<span class=\"loop\" id=\"loop-1\" title=\"kept, its body does I/O\">",
+            "[.<span class=\"zero\" id=\"loop-2\" title=\"only adds an odd amount to the current cell, so it sets it to zero\">[-]</span>&gt;]</span></pre>"
+        )));
+        assert!(html.contains("<td>2:2</td><td>loop</td><td>kept, its body does I/O</td><td class=\"source\">[.[-]&gt;]</td></tr>"));
+        assert!(!html.contains("Profile"));
+    }
+
+    #[test]
+    fn includes_profile_counts() {
+        let source = "++[>+<-]>[--]";
+        let result = profile(source, &mut &b""[..], &mut Vec::new(), &Limits::default(), &ParseOptions::default()).unwrap();
+        let html = report(source, &ParseOptions::default(), Some(&result)).unwrap();
+        assert!(html.contains("<td>kept, the current cell changes by -2 per iteration, which may never reach zero</td><td class=\"source\">[--]</td><td>1 iterations, 3 steps</td></tr>"));
+        assert!(html.contains("<td>1 times</td></tr>"));
+        assert!(html.contains("<h2>Profile</h2>\n<p>Executed "));
+    }
+}
@@ -0,0 +1,116 @@
+//! Tape snapshots, so long-running programs can be inspected and resumed
+//! from a known state.
+//!
+//! A snapshot is stored as the magic `BFSNAP1\n` followed by three
+//! little-endian `u64`s (source offset of the next step or `u64::MAX` when
+//! there is none, data pointer, number of stored cells) and the cells
+//! themselves. Trailing zero cells are not stored.
+
+use std::io::{Read, Write};
+
+use super::error::Bf2cError;
+use super::interp::{Tape, TAPE_SIZE};
+
+const MAGIC: &[u8; 8] = b"BFSNAP1\n";
+
+pub struct Snapshot {
+    pub tape: Tape,
+    /// Source offset of the next step. `None` when the snapshot was taken
+    /// after the program finished, in which case resuming starts over with
+    /// the saved tape.
+    pub offset: Option<usize>,
+}
+
+impl Snapshot {
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<(), Bf2cError> {
+        let used = self.tape.cells.iter().rposition(|&cell| cell != 0).map_or(0, |last| last + 1);
+        let offset = self.offset.map_or(u64::MAX, |offset| offset as u64);
+        let mut buf = Vec::with_capacity(MAGIC.len() + 24 + used);
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&offset.to_le_bytes());
+        buf.extend_from_slice(&(self.tape.ptr as u64).to_le_bytes());
+        buf.extend_from_slice(&(used as u64).to_le_bytes());
+        buf.extend_from_slice(&self.tape.cells[..used]);
+        out.write_all(&buf).map_err(|e| Bf2cError::io("write snapshot", e))
+    }
+
+    pub fn read<R: Read>(input: &mut R) -> Result<Snapshot, Bf2cError> {
+        let mut buf = Vec::new();
+        input.read_to_end(&mut buf).map_err(|e| Bf2cError::io("read snapshot", e))?;
+        let header = MAGIC.len() + 24;
+        if buf.len() < header || &buf[..MAGIC.len()] != MAGIC {
+            return Err(Bf2cError::Invalid("not a tape snapshot".to_string()));
+        }
+        let field = |i: usize| {
+            let start = MAGIC.len() + 8 * i;
+            u64::from_le_bytes(buf[start..start + 8].try_into().unwrap())
+        };
+        let (offset, ptr, used) = (field(0), field(1), field(2));
+        if ptr >= TAPE_SIZE as u64 || used > TAPE_SIZE as u64 || buf.len() as u64 != header as u64 + used {
+            return Err(Bf2cError::Invalid("corrupt tape snapshot".to_string()));
+        }
+        let mut tape = Tape::new();
+        tape.ptr = ptr as usize;
+        tape.cells[..used as usize].copy_from_slice(&buf[header..]);
+        let offset = if offset == u64::MAX { None } else { Some(offset as usize) };
+        Ok(Snapshot { tape, offset })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bf2c::ParseOptions;
+    use super::super::interp::{Machine, Tape};
+    use super::Snapshot;
+
+    #[test]
+    fn round_trip() {
+        let mut tape = Tape::new();
+        tape.cells[3] = 7;
+        tape.ptr = 5;
+        let mut buf = Vec::new();
+        Snapshot { tape, offset: Some(12) }.write(&mut buf).unwrap();
+        // header plus the four cells up to the last non-zero one
+        assert_eq!(buf.len(), 32 + 4);
+
+        let snapshot = Snapshot::read(&mut &buf[..]).unwrap();
+        assert_eq!(snapshot.offset, Some(12));
+        assert_eq!(snapshot.tape.ptr, 5);
+        assert_eq!(&snapshot.tape.cells[..5], &[0, 0, 0, 7, 0]);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(Snapshot::read(&mut &b"hello"[..]).is_err());
+        let mut buf = Vec::new();
+        Snapshot { tape: Tape::new(), offset: None }.write(&mut buf).unwrap();
+        buf.push(1);
+        assert!(Snapshot::read(&mut &buf[..]).is_err());
+    }
+
+    #[test]
+    fn resume_mid_program() {
+        let source = "+++ . [-] ++ .";
+        let mut machine = Machine::from_source(source, false, &ParseOptions::default()).unwrap();
+        let mut out = Vec::new();
+        for _ in 0..4 {
+            machine.step(&mut &b""[..], &mut out).unwrap();
+        }
+        let snapshot = machine.snapshot();
+        assert_eq!(snapshot.offset, Some(6));
+
+        let mut resumed = Machine::from_source(source, true, &ParseOptions::default()).unwrap();
+        resumed.restore(snapshot).unwrap();
+        resumed.run(&mut &b""[..], &mut out, &Default::default()).unwrap();
+        assert_eq!(out, vec![3, 2]);
+    }
+
+    #[test]
+    fn resume_inside_coalesced_statement_fails() {
+        let mut machine = Machine::from_source("+++", false, &ParseOptions::default()).unwrap();
+        machine.step(&mut &b""[..], &mut Vec::new()).unwrap();
+        let snapshot = machine.snapshot();
+        let mut resumed = Machine::from_source("+++", true, &ParseOptions::default()).unwrap();
+        assert!(resumed.restore(snapshot).is_err());
+    }
+}
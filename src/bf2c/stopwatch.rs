@@ -0,0 +1,26 @@
+//! Timing of the stages logged with `tracing`.
+
+use core::time::Duration;
+
+/// Wall clock started at `start`. Without `std` there is no clock, and
+/// everything takes no time.
+pub(crate) struct Stopwatch {
+    #[cfg(feature = "std")]
+    start: std::time::Instant,
+}
+
+impl Stopwatch {
+    pub(crate) fn start() -> Self {
+        Stopwatch {
+            #[cfg(feature = "std")]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        #[cfg(feature = "std")]
+        return self.start.elapsed();
+        #[cfg(not(feature = "std"))]
+        return Duration::ZERO;
+    }
+}
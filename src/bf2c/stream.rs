@@ -0,0 +1,239 @@
+//! Tokenizer for plain Brainfuck that reads its source in chunks, so huge
+//! generated programs never have to be held in memory as text. Other dialects
+//! and literate sources are rejected, since they are only parsed as text.
+
+#[cfg(feature = "mmap")]
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+#[cfg(feature = "mmap")]
+use std::path::Path;
+
+use super::bf2c::{BfSymbol, ParseError, ParseOptions, Span};
+use super::dialect::Dialect;
+use super::error::Bf2cError;
+
+/// Bytes read from the source at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Iterator over the tokens of a source read from `R`, with the same spans
+/// as `parse_spanned`. A read or bracket error is the last item.
+pub struct Tokens<R> {
+    reader: R,
+    buf: Box<[u8]>,
+    /// Next unread byte of `buf`, and the number of bytes in it.
+    pos: usize,
+    len: usize,
+    offset: usize,
+    line: usize,
+    column: usize,
+    debug: bool,
+    /// Spans of the open brackets, if verifying.
+    open: Option<Vec<Span>>,
+    finished: bool,
+}
+
+/// Tokens of the Brainfuck source in `reader`. If `verify` is set, brackets
+/// are checked as they go by: an unmatched `]` ends the stream with an error
+/// right away, and an unclosed `[` once the end of the source is reached.
+///
+/// Of `options`, only `debug` is supported. Any other dialect, or a literate
+/// source, fails with `Bf2cError::Invalid`.
+pub fn tokenize<R: Read>(reader: R, verify: bool, options: &ParseOptions) -> Result<Tokens<R>, Bf2cError> {
+    if options.dialect != Dialect::Brainfuck {
+        return Err(Bf2cError::Invalid("only plain Brainfuck can be tokenized as a stream".to_string()));
+    }
+    if options.literate {
+        return Err(Bf2cError::Invalid("literate sources cannot be tokenized as a stream".to_string()));
+    }
+    Ok(Tokens {
+        reader,
+        buf: vec![0; CHUNK_SIZE].into_boxed_slice(),
+        pos: 0,
+        len: 0,
+        offset: 0,
+        line: 1,
+        column: 1,
+        debug: options.debug,
+        open: verify.then(Vec::new),
+        finished: false,
+    })
+}
+
+/// A source file mapped into memory by `map_file`.
+#[cfg(feature = "mmap")]
+pub struct MappedSource {
+    map: memmap2::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedSource {
+    pub fn bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// Tokens of the file, as by `tokenize`.
+    pub fn tokenize(&self, verify: bool, options: &ParseOptions) -> Result<Tokens<&[u8]>, Bf2cError> {
+        tokenize(self.bytes(), verify, options)
+    }
+}
+
+/// Maps the source file at `path` into memory, so that tokenizing it needs
+/// neither a copy of the text nor more than one chunk of it in memory at a
+/// time. The file must not be changed while it is mapped.
+#[cfg(feature = "mmap")]
+pub fn map_file(path: &Path) -> Result<MappedSource, Bf2cError> {
+    let file = File::open(path).map_err(|e| Bf2cError::io("open source", e))?;
+    // SAFETY: the mapping is read-only, and callers must not change the
+    // file while it is mapped
+    let map = unsafe { memmap2::Mmap::map(&file) }.map_err(|e| Bf2cError::io("map source", e))?;
+    Ok(MappedSource { map })
+}
+
+impl<R: Read> Tokens<R> {
+    /// Reads the next chunk. Returns false at the end of the source.
+    fn fill(&mut self) -> Result<bool, Bf2cError> {
+        loop {
+            match self.reader.read(&mut self.buf) {
+                Ok(n) => {
+                    self.pos = 0;
+                    self.len = n;
+                    return Ok(n > 0);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(Bf2cError::io("read source", e)),
+            }
+        }
+    }
+
+    fn fail(&mut self, e: Bf2cError) -> Option<Result<(BfSymbol, Span), Bf2cError>> {
+        self.finished = true;
+        Some(Err(e))
+    }
+}
+
+impl<R: Read> Iterator for Tokens<R> {
+    type Item = Result<(BfSymbol, Span), Bf2cError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.finished {
+            if self.pos == self.len {
+                match self.fill() {
+                    Ok(true) => continue,
+                    Ok(false) => {
+                        self.finished = true;
+                        // the innermost bracket is reported, as by `parse`
+                        let last = self.open.as_mut().and_then(|open| open.pop())?;
+                        let e = ParseError::UnclosedOpen { offset: last.start, line: last.line, column: last.column };
+                        return Some(Err(e.into()));
+                    }
+                    Err(e) => return self.fail(e),
+                }
+            }
+            let byte = self.buf[self.pos];
+            self.pos += 1;
+            let span = Span { start: self.offset, end: self.offset + 1, line: self.line, column: self.column };
+            self.offset += 1;
+            match byte {
+                b'\n' => {
+                    self.line += 1;
+                    self.column = 1;
+                }
+                // UTF-8 continuation bytes are part of the previous character
+                0x80..=0xbf => {}
+                _ => self.column += 1,
+            }
+            let symbol = match byte {
+                b'<' => BfSymbol::Left,
+                b'>' => BfSymbol::Right,
+                b'+' => BfSymbol::Plus,
+                b'-' => BfSymbol::Minus,
+                b'.' => BfSymbol::Period,
+                b',' => BfSymbol::Comma,
+                b'[' => BfSymbol::OpenBracket,
+                b']' => BfSymbol::CloseBracket,
+                b'#' if self.debug => BfSymbol::Debug,
+                _ => continue,
+            };
+            if let Some(open) = self.open.as_mut() {
+                match symbol {
+                    BfSymbol::OpenBracket => open.push(span),
+                    BfSymbol::CloseBracket if open.pop().is_none() => {
+                        let e = ParseError::UnmatchedClose { offset: span.start, line: span.line, column: span.column };
+                        return self.fail(e.into());
+                    }
+                    _ => {}
+                }
+            }
+            return Some(Ok((symbol, span)));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bf2c::{parse, parse_spanned, ParseError, ParseOptions};
+    use super::super::dialect::Dialect;
+    use super::super::error::Bf2cError;
+    use super::tokenize;
+    use std::io::Read;
+
+    /// Hands out at most three bytes per read, to split tokens and
+    /// characters across chunks.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn matches_parse_spanned() {
+        let source = "+ é[\n  -> ü,\n]<<.#";
+        let streamed: Vec<_> = tokenize(Trickle(source.as_bytes()), true, &ParseOptions::default()).unwrap().map(Result::unwrap).collect();
+        assert_eq!(streamed, parse_spanned(source, &ParseOptions::default()).unwrap());
+    }
+
+    #[test]
+    fn bracket_errors_end_the_stream() {
+        for source in ["+\n é]", "[\n[[]"] {
+            let expected = parse(source, true).unwrap_err();
+            let last = tokenize(Trickle(source.as_bytes()), true, &ParseOptions::default()).unwrap().last().unwrap();
+            assert!(matches!(last, Err(Bf2cError::Parse(e)) if e == expected));
+        }
+        let tokens: Vec<_> = tokenize(&b"]]+"[..], true, &ParseOptions::default()).unwrap().collect();
+        assert_eq!(tokens.len(), 1);
+        assert!(matches!(tokens[0], Err(Bf2cError::Parse(ParseError::UnmatchedClose { offset: 0, .. }))));
+        // without verification the brackets are just tokens
+        let debug = ParseOptions { debug: true, ..ParseOptions::default() };
+        assert_eq!(tokenize(&b"]]+#"[..], false, &debug).unwrap().filter(Result::is_ok).count(), 4);
+    }
+
+    #[test]
+    fn rejects_what_only_the_parser_reads() {
+        for options in [
+            ParseOptions { dialect: Dialect::Ook, ..ParseOptions::default() },
+            ParseOptions { literate: true, ..ParseOptions::default() },
+        ] {
+            assert!(matches!(tokenize(&b"+"[..], true, &options), Err(Bf2cError::Invalid(_))));
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn tokenizes_mapped_files() {
+        let path = std::env::temp_dir().join(format!("bf-mmap-{}.bf", std::process::id()));
+        for source in ["+[->+<]\n.", ""] {
+            std::fs::write(&path, source).unwrap();
+            let mapped = super::map_file(&path).unwrap();
+            let tokens: Vec<_> = mapped.tokenize(true, &ParseOptions::default()).unwrap().map(Result::unwrap).collect();
+            assert_eq!(tokens, parse_spanned(source, &ParseOptions::default()).unwrap());
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(super::map_file(&path), Err(Bf2cError::Io { .. })));
+    }
+}
@@ -0,0 +1,339 @@
+//! Execution traces recorded by the interpreter's `Machine`.
+//!
+//! A trace is a text file with one event per line, `<kind> <offset> <ptr>`
+//! followed by the byte for I/O events:
+//!
+//! ```text
+//! x 0 0       step at source offset 0, pointer on cell 0
+//! [ 3 0       loop at offset 3 entered
+//! ] 9 0       loop closed at offset 9 exited
+//! i 10 0 97   input stored 97 in cell 0
+//! o 11 0 97   output 97 from cell 0
+//! ```
+//!
+//! I/O events do not depend on how the program was optimized, so comparing
+//! them between two traces finds where two runs start to disagree.
+
+use std::fmt;
+use std::io::{BufRead, Read, Write};
+use std::str::FromStr;
+
+use super::error::Bf2cError;
+
+/// Which events a `Tracer` records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFilter {
+    /// Every step, loop entry and exit, and I/O byte.
+    All,
+    /// Loop entries and exits and I/O bytes.
+    Loops,
+    /// I/O bytes only.
+    Io,
+}
+
+impl FromStr for TraceFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "all" => Ok(TraceFilter::All),
+            "loops" => Ok(TraceFilter::Loops),
+            "io" => Ok(TraceFilter::Io),
+            _ => Err(format!("unknown trace filter '{}', expected all, loops or io", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Step,
+    LoopEnter,
+    LoopExit,
+    Input,
+    Output,
+}
+
+impl EventKind {
+    fn tag(self) -> &'static str {
+        match self {
+            EventKind::Step => "x",
+            EventKind::LoopEnter => "[",
+            EventKind::LoopExit => "]",
+            EventKind::Input => "i",
+            EventKind::Output => "o",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            EventKind::Step => "step",
+            EventKind::LoopEnter => "enter",
+            EventKind::LoopExit => "exit",
+            EventKind::Input => "input",
+            EventKind::Output => "output",
+        }
+    }
+
+    fn is_io(self) -> bool {
+        matches!(self, EventKind::Input | EventKind::Output)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    /// Source offset of the step that caused the event.
+    pub offset: usize,
+    /// Data pointer when the step started.
+    pub ptr: usize,
+    /// Byte read or written, for I/O events.
+    pub value: Option<u8>,
+}
+
+impl Event {
+    /// Parses one line of a trace file.
+    pub fn parse(line: &str) -> Result<Event, Bf2cError> {
+        let invalid = || Bf2cError::Invalid(format!("invalid trace event '{}'", line));
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let kind = match fields.first() {
+            Some(&"x") => EventKind::Step,
+            Some(&"[") => EventKind::LoopEnter,
+            Some(&"]") => EventKind::LoopExit,
+            Some(&"i") => EventKind::Input,
+            Some(&"o") => EventKind::Output,
+            _ => return Err(invalid()),
+        };
+        let expected = if kind.is_io() { 4 } else { 3 };
+        if fields.len() != expected {
+            return Err(invalid());
+        }
+        let offset = fields[1].parse().map_err(|_| invalid())?;
+        let ptr = fields[2].parse().map_err(|_| invalid())?;
+        let value = match fields.get(3) {
+            Some(value) => Some(value.parse().map_err(|_| invalid())?),
+            None => None,
+        };
+        Ok(Event { kind, offset, ptr, value })
+    }
+
+    /// Human-readable form, showing the instruction at the event's offset
+    /// when the traced `source` is known.
+    pub fn describe(&self, source: Option<&str>) -> String {
+        let mut line = format!("{:>8}  {:>6}  {:<6}", self.offset, self.ptr, self.kind.name());
+        if let Some(value) = self.value {
+            line += &format!(" {:>3}", value);
+            if value.is_ascii_graphic() || value == b' ' {
+                line += &format!(" '{}'", value as char);
+            }
+        } else if let Some(c) = source.and_then(|source| source.get(self.offset..)?.chars().next()) {
+            line += &format!(" {}", c);
+        }
+        line
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.kind.tag(), self.offset, self.ptr)?;
+        if let Some(value) = self.value {
+            write!(f, " {}", value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the events selected by its filter, one per line.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    filter: TraceFilter,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, filter: TraceFilter) -> Self {
+        Tracer { out, filter }
+    }
+
+    pub fn record(&mut self, event: Event) -> Result<(), Bf2cError> {
+        let wanted = match event.kind {
+            EventKind::Step => self.filter == TraceFilter::All,
+            EventKind::LoopEnter | EventKind::LoopExit => self.filter != TraceFilter::Io,
+            EventKind::Input | EventKind::Output => true,
+        };
+        if wanted {
+            writeln!(self.out, "{}", event).map_err(|e| Bf2cError::io("write trace", e))?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Bf2cError> {
+        self.out.flush().map_err(|e| Bf2cError::io("write trace", e))
+    }
+}
+
+/// Reader or writer that keeps a copy of the bytes going through it, so a
+/// step's I/O can be traced byte by byte.
+pub(crate) struct Recording<T> {
+    inner: T,
+    pub(crate) bytes: Vec<u8>,
+}
+
+impl<T> Recording<T> {
+    pub(crate) fn new(inner: T) -> Self {
+        Recording { inner, bytes: Vec::new() }
+    }
+}
+
+impl<T: Read> Read for Recording<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        // end of input stores 255, see `interp::read_byte`
+        self.bytes.extend_from_slice(if n == 0 { &[255] } else { &buf[..n] });
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Recording<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads a trace file written by a `Tracer`.
+pub fn read_trace<R: BufRead>(input: R) -> Result<Vec<Event>, Bf2cError> {
+    let mut events = Vec::new();
+    for line in input.lines() {
+        let line = line.map_err(|e| Bf2cError::io("read trace", e))?;
+        if !line.trim().is_empty() {
+            events.push(Event::parse(&line)?);
+        }
+    }
+    Ok(events)
+}
+
+/// How two traces compare on their I/O events.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Divergence {
+    /// Both traces performed the same I/O.
+    None { events: usize },
+    /// The `index`th I/O event differs, or is missing from one trace.
+    At { index: usize, left: Option<Event>, right: Option<Event> },
+}
+
+/// Compares the I/O events of two traces, ignoring source offsets so traces
+/// of the same program at different optimization levels can be compared.
+pub fn compare_io(left: &[Event], right: &[Event]) -> Divergence {
+    let mut left = left.iter().filter(|event| event.kind.is_io());
+    let mut right = right.iter().filter(|event| event.kind.is_io());
+    let mut index = 0;
+    loop {
+        match (left.next(), right.next()) {
+            (None, None) => return Divergence::None { events: index },
+            (Some(a), Some(b)) if (a.kind, a.ptr, a.value) == (b.kind, b.ptr, b.value) => index += 1,
+            (a, b) => return Divergence::At { index, left: a.copied(), right: b.copied() },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bf2c::ParseOptions;
+    use super::super::interp::{Limits, Machine};
+    use super::{compare_io, read_trace, Divergence, Event, EventKind, TraceFilter, Tracer};
+    use std::fs::{self, File};
+    use std::io::BufReader;
+
+    /// Runs `src` with a tracer writing to a temporary file named after `tag`.
+    fn traced(tag: &str, src: &str, statements: bool, filter: TraceFilter, input: &[u8]) -> Vec<Event> {
+        let path = std::env::temp_dir().join(format!("bf-trace-{}-{}", tag, std::process::id()));
+        let mut machine = Machine::from_source(src, statements, &ParseOptions::default()).unwrap();
+        machine.set_tracer(Tracer::new(Box::new(File::create(&path).unwrap()), filter));
+        machine.run(&mut &input[..], &mut Vec::new(), &Limits::default()).unwrap();
+        drop(machine);
+        let events = read_trace(BufReader::new(File::open(&path).unwrap())).unwrap();
+        fs::remove_file(&path).unwrap();
+        events
+    }
+
+    fn event(kind: EventKind, offset: usize, ptr: usize, value: Option<u8>) -> Event {
+        Event { kind, offset, ptr, value }
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let text = "x 0 0\n[ 3 1\n] 9 1\ni 10 2 97\no 11 2 97\n";
+        let events = read_trace(text.as_bytes()).unwrap();
+        assert_eq!(events[1], event(EventKind::LoopEnter, 3, 1, None));
+        assert_eq!(events[3], event(EventKind::Input, 10, 2, Some(97)));
+        let written: String = events.iter().map(|e| format!("{}\n", e)).collect();
+        assert_eq!(written, text);
+    }
+
+    #[test]
+    fn describe_events() {
+        let source = "+.";
+        assert_eq!(event(EventKind::Step, 0, 2, None).describe(Some(source)), "       0       2  step   +");
+        assert_eq!(event(EventKind::Output, 1, 2, Some(65)).describe(None), "       1       2  output  65 'A'");
+        assert_eq!(event(EventKind::Output, 1, 2, Some(10)).describe(None), "       1       2  output  10");
+    }
+
+    #[test]
+    fn rejects_malformed_events() {
+        assert!(Event::parse("o 1 2").is_err());
+        assert!(Event::parse("x 1 2 3").is_err());
+        assert!(Event::parse("? 1 2").is_err());
+        assert!(Event::parse("i 1 2 256").is_err());
+    }
+
+    #[test]
+    fn compare_ignores_offsets_and_steps() {
+        let raw = [
+            event(EventKind::Step, 0, 0, None),
+            event(EventKind::Output, 1, 0, Some(1)),
+            event(EventKind::Output, 2, 0, Some(1)),
+        ];
+        let optimized = [event(EventKind::Output, 1, 0, Some(1)), event(EventKind::Output, 1, 0, Some(1))];
+        assert_eq!(compare_io(&raw, &optimized), Divergence::None { events: 2 });
+
+        let wrong = [event(EventKind::Output, 1, 0, Some(1)), event(EventKind::Output, 1, 0, Some(2))];
+        assert_eq!(
+            compare_io(&raw, &wrong),
+            Divergence::At { index: 1, left: Some(raw[2]), right: Some(wrong[1]) }
+        );
+        assert_eq!(
+            compare_io(&raw, &wrong[..1]),
+            Divergence::At { index: 1, left: Some(raw[2]), right: None }
+        );
+    }
+
+    #[test]
+    fn records_filtered_events() {
+        let events = traced("all", ",[.-]", false, TraceFilter::All, b"\x02");
+        let kinds: Vec<EventKind> = events.iter().map(|e| e.kind).collect();
+        use EventKind::*;
+        assert_eq!(
+            kinds,
+            vec![Step, Input, LoopEnter, Step, Output, Step, Step, Output, Step, LoopExit]
+        );
+        assert_eq!(events[4], event(Output, 2, 0, Some(2)));
+
+        let events = traced("loops", ",[.-]", false, TraceFilter::Loops, b"\x02");
+        assert_eq!(events.len(), 5);
+        let events = traced("io", ">,.", false, TraceFilter::Io, b"");
+        assert_eq!(events, vec![event(Input, 1, 1, Some(255)), event(Output, 2, 1, Some(255))]);
+    }
+
+    #[test]
+    fn optimization_levels_agree() {
+        let src = "+++[>++<-]>..,.";
+        let raw = traced("raw", src, false, TraceFilter::All, b"z");
+        let optimized = traced("ir", src, true, TraceFilter::All, b"z");
+        assert!(raw.len() > optimized.len());
+        assert_eq!(compare_io(&raw, &optimized), Divergence::None { events: 4 });
+    }
+}
@@ -0,0 +1,569 @@
+//! The configurable entry point for turning Brainfuck into C.
+//!
+//! ```
+//! use cbt_fuck::bf2c::transpiler::{CellWidth, Transpiler};
+//!
+//! let transpiler = Transpiler::builder().tape_size(65536).cell_width(CellWidth::U16).opt_level(1).build().unwrap();
+//! let code = transpiler.transpile("+[->+<]").unwrap();
+//! assert!(code.contains("uint16_t tape[65536];"));
+//! ```
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::time::Duration;
+
+use tracing::{debug, debug_span, field, info, info_span};
+
+use super::bf2c::{debug_dump, emit_tokens, parse_with_options, wrap_boilerplate, BfSymbol, ParseError, ParseOptions, Span};
+use super::error::Bf2cError;
+use super::localop::{optimize, IrPass, Prog, Stmt};
+use super::stopwatch::Stopwatch;
+#[cfg(feature = "mmap")]
+use super::stream::map_file;
+#[cfg(feature = "std")]
+use super::stream::tokenize;
+
+/// Number of cells on the tape unless configured otherwise, in the generated
+/// C code and in the interpreter.
+pub const TAPE_SIZE: usize = 200000;
+
+/// Size of a tape cell. Cells wrap around on overflow. Only `U8` matches the
+/// interpreter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CellWidth {
+    /// A plain `char`, as in most Brainfuck implementations.
+    #[default]
+    U8,
+    U16,
+    U32,
+}
+
+impl CellWidth {
+    /// C type of a cell.
+    pub(crate) fn c_type(self) -> &'static str {
+        match self {
+            CellWidth::U8 => "char",
+            CellWidth::U16 => "uint16_t",
+            CellWidth::U32 => "uint32_t",
+        }
+    }
+
+    /// Unsigned C type of the same width.
+    fn unsigned_type(self) -> &'static str {
+        match self {
+            CellWidth::U8 => "unsigned char",
+            _ => self.c_type(),
+        }
+    }
+
+    fn mask(self) -> u64 {
+        match self {
+            CellWidth::U8 => 0xff,
+            CellWidth::U16 => 0xffff,
+            CellWidth::U32 => 0xffff_ffff,
+        }
+    }
+}
+
+/// Language the program is translated to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    C,
+}
+
+/// Shape of the tape in the generated program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Target {
+    pub(crate) tape_size: usize,
+    pub(crate) cell_width: CellWidth,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Target { tape_size: TAPE_SIZE, cell_width: CellWidth::U8 }
+    }
+}
+
+/// Bounds on the work done for one source, for transpiling untrusted input.
+/// Exceeding one ends `transpile` with `Bf2cError::Limit`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Instructions in the source.
+    pub max_tokens: Option<usize>,
+    /// Statements in the IR, counting the bodies of loops, after optimization
+    /// and after every pass.
+    pub max_ir_nodes: Option<usize>,
+    /// Size of the generated program.
+    pub max_output_bytes: Option<usize>,
+    /// Time for the whole call, checked after every phase. Without `std`
+    /// there is no clock and this is never exceeded.
+    pub deadline: Option<Duration>,
+}
+
+impl Limits {
+    fn check(limit: Option<usize>, value: usize, what: &str) -> Result<(), Bf2cError> {
+        match limit {
+            Some(limit) if value > limit => Err(Bf2cError::Limit(format!("{} limit of {} exceeded", what, limit))),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_deadline(&self, clock: &Stopwatch, phase: &str) -> Result<(), Bf2cError> {
+        match self.deadline {
+            Some(deadline) if clock.elapsed() > deadline => Err(Bf2cError::Limit(format!("deadline of {:?} exceeded after {}", deadline, phase))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Translates Brainfuck sources with one configuration. The default matches
+/// the interpreter: `TAPE_SIZE` cells of 8 bits and no optimization. The
+/// interpreter, the JIT and the `bf!` macros only have 8-bit cells, so
+/// programs built with any other `CellWidth` behave like them only as long as
+/// no cell wraps around and no input is read past its end.
+#[derive(Debug, Clone, Default)]
+pub struct Transpiler {
+    options: ParseOptions,
+    target: Target,
+    opt_level: u8,
+    backend: Backend,
+    passes: Vec<Arc<dyn IrPass>>,
+    limits: Limits,
+}
+
+impl Transpiler {
+    pub fn builder() -> TranspilerBuilder {
+        TranspilerBuilder { transpiler: Transpiler::default() }
+    }
+
+    pub fn options(&self) -> &ParseOptions {
+        &self.options
+    }
+
+    pub fn cell_width(&self) -> CellWidth {
+        self.target.cell_width
+    }
+
+    pub fn opt_level(&self) -> u8 {
+        self.opt_level
+    }
+
+    /// The generated program for `source`.
+    pub fn transpile(&self, source: &str) -> Result<String, Bf2cError> {
+        let _span = info_span!("transpile", bytes = source.len(), opt_level = self.opt_level).entered();
+        let clock = Stopwatch::start();
+        let (tokens, _) = parse_with_options(source, true, &self.options)?;
+        Limits::check(self.limits.max_tokens, tokens.len(), "token")?;
+        self.limits.check_deadline(&clock, "parsing")?;
+        self.generate(&tokens, &clock)
+    }
+
+    /// The generated program for the source in `reader`, which is tokenized
+    /// in chunks by `stream::tokenize` instead of being read as text. Only
+    /// plain Brainfuck can be read this way, other `options` fail with
+    /// `Bf2cError::Invalid`.
+    #[cfg(feature = "std")]
+    pub fn transpile_reader(&self, reader: impl std::io::Read) -> Result<String, Bf2cError> {
+        self.transpile_tokens(tokenize(reader, true, &self.options)?)
+    }
+
+    /// The generated program for the source file at `path`, which is mapped
+    /// into memory by `stream::map_file` and tokenized from there, as by
+    /// `transpile_reader`. The file must not be changed meanwhile.
+    #[cfg(feature = "mmap")]
+    pub fn transpile_mapped(&self, path: &std::path::Path) -> Result<String, Bf2cError> {
+        let source = map_file(path)?;
+        self.transpile_tokens(source.tokenize(true, &self.options)?)
+    }
+
+    /// The generated program for a stream of tokens, such as those of
+    /// `stream::tokenize`. The first error in the stream is returned, and the
+    /// brackets are checked, as by `transpile`. The token limit is checked as
+    /// the tokens arrive, so an oversized stream is not read to its end.
+    pub fn transpile_tokens(&self, tokens: impl IntoIterator<Item = Result<(BfSymbol, Span), Bf2cError>>) -> Result<String, Bf2cError> {
+        let _span = info_span!("transpile", opt_level = self.opt_level).entered();
+        let clock = Stopwatch::start();
+        let (mut symbols, mut open) = (Vec::new(), Vec::new());
+        for token in tokens {
+            let (symbol, span) = token?;
+            match symbol {
+                BfSymbol::OpenBracket => open.push(span),
+                BfSymbol::CloseBracket if open.pop().is_none() => {
+                    return Err(ParseError::UnmatchedClose { offset: span.start, line: span.line, column: span.column }.into());
+                }
+                _ => {}
+            }
+            symbols.push(symbol);
+            Limits::check(self.limits.max_tokens, symbols.len(), "token")?;
+        }
+        // the innermost bracket is reported, as by `parse`
+        if let Some(last) = open.pop() {
+            return Err(ParseError::UnclosedOpen { offset: last.start, line: last.line, column: last.column }.into());
+        }
+        self.limits.check_deadline(&clock, "parsing")?;
+        self.generate(&symbols, &clock)
+    }
+
+    /// The generated program for the parsed `tokens`.
+    fn generate(&self, tokens: &[BfSymbol], clock: &Stopwatch) -> Result<String, Bf2cError> {
+        let prog = if self.opt_level > 0 { Some(self.optimize(tokens, clock)?) } else { None };
+        let span = info_span!("emit", tokens = tokens.len(), bytes = field::Empty).entered();
+        let start = Stopwatch::start();
+        let code = match (self.backend, &prog) {
+            (Backend::C, None) => wrap_boilerplate(emit_tokens(tokens, &self.target), &self.target),
+            (Backend::C, Some(prog)) => wrap_boilerplate(emit_prog(prog, &self.target), &self.target),
+        };
+        span.record("bytes", code.len());
+        info!(tokens = tokens.len(), bytes = code.len(), elapsed = ?start.elapsed(), "emitted C");
+        Limits::check(self.limits.max_output_bytes, code.len(), "output byte")?;
+        self.limits.check_deadline(clock, "emission")?;
+        Ok(code)
+    }
+
+    /// The IR of `tokens`, after the registered passes.
+    fn optimize(&self, tokens: &[BfSymbol], clock: &Stopwatch) -> Result<Prog, Bf2cError> {
+        let mut prog = optimize(tokens);
+        Limits::check(self.limits.max_ir_nodes, count_nodes(&prog), "IR node")?;
+        self.limits.check_deadline(clock, "optimization")?;
+        for pass in &self.passes {
+            let _span = debug_span!("pass", name = pass.name(), statements = prog.len()).entered();
+            let start = Stopwatch::start();
+            pass.run(&mut prog);
+            debug!(pass = pass.name(), statements = prog.len(), elapsed = ?start.elapsed(), "ran IR pass");
+            Limits::check(self.limits.max_ir_nodes, count_nodes(&prog), "IR node")?;
+            self.limits.check_deadline(clock, pass.name())?;
+        }
+        Ok(prog)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TranspilerBuilder {
+    transpiler: Transpiler,
+}
+
+impl TranspilerBuilder {
+    /// Dialect and extensions of the sources.
+    pub fn options(mut self, options: ParseOptions) -> Self {
+        self.transpiler.options = options;
+        self
+    }
+
+    /// Number of cells on the tape.
+    pub fn tape_size(mut self, cells: usize) -> Self {
+        self.transpiler.target.tape_size = cells;
+        self
+    }
+
+    pub fn cell_width(mut self, width: CellWidth) -> Self {
+        self.transpiler.target.cell_width = width;
+        self
+    }
+
+    /// 0 emits one statement per instruction, 1 and above emit the IR of
+    /// `localop`, with runs of instructions coalesced and simple loops
+    /// replaced by their effect.
+    pub fn opt_level(mut self, level: u8) -> Self {
+        self.transpiler.opt_level = level;
+        self
+    }
+
+    /// Adds a pass to run on the IR after the built-in optimizations. Passes
+    /// only run at `opt_level` 1 and above.
+    pub fn pass(mut self, pass: impl IrPass + 'static) -> Self {
+        self.transpiler.passes.push(Arc::new(pass));
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.transpiler.limits = limits;
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.transpiler.backend = backend;
+        self
+    }
+
+    pub fn build(self) -> Result<Transpiler, Bf2cError> {
+        if self.transpiler.target.tape_size == 0 {
+            return Err(Bf2cError::Invalid("the tape needs at least one cell".to_string()));
+        }
+        Ok(self.transpiler)
+    }
+}
+
+/// Number of statements in `prog`, including those in loop bodies.
+fn count_nodes(prog: &Prog) -> usize {
+    prog.iter()
+        .map(|stmt| match stmt {
+            Stmt::Loop(body) => 1 + count_nodes(body),
+            _ => 1,
+        })
+        .sum()
+}
+
+/// C statements for `prog`, indented to sit inside `main`.
+fn emit_prog(prog: &Prog, target: &Target) -> String {
+    let mut out = String::new();
+    emit_block(&mut out, prog, target, 1);
+    out
+}
+
+fn emit_block(out: &mut String, prog: &Prog, target: &Target, depth: usize) {
+    let indent = "    ".repeat(depth);
+    for stmt in prog {
+        match stmt {
+            Stmt::Add(delta) if *delta < 0 => writeln!(out, "{}*ptr -= {};", indent, -delta).unwrap(),
+            Stmt::Add(delta) => writeln!(out, "{}*ptr += {};", indent, delta).unwrap(),
+            Stmt::Move(distance) if *distance < 0 => writeln!(out, "{}ptr -= {};", indent, -distance).unwrap(),
+            Stmt::Move(distance) => writeln!(out, "{}ptr += {};", indent, distance).unwrap(),
+            Stmt::Output(count) => {
+                for _ in 0..*count {
+                    writeln!(out, "{}putchar(*ptr);", indent).unwrap();
+                }
+            }
+            Stmt::Input(count) => {
+                for _ in 0..*count {
+                    writeln!(out, "{}*ptr = getchar();", indent).unwrap();
+                }
+            }
+            Stmt::Loop(body) => {
+                writeln!(out, "{}while (*ptr) {{", indent).unwrap();
+                emit_block(out, body, target, depth + 1);
+                writeln!(out, "{}}}", indent).unwrap();
+            }
+            Stmt::ZeroLoop => writeln!(out, "{}*ptr = 0;", indent).unwrap(),
+            Stmt::ScanLoop(direction) => writeln!(out, "{}while (*ptr) ptr += {};", indent, direction).unwrap(),
+            Stmt::MultiplicationLoop(decrement, effects) => {
+                let cell = target.cell_width.unsigned_type();
+                // the loop runs until the cell wraps to zero, which takes
+                // the cell times the inverse of the decrement iterations
+                let iterations = match *decrement {
+                    1 => format!("({})*ptr", cell),
+                    d => format!("({})((unsigned long)*ptr * {}ul)", cell, inverse_mod_2_64(d as i64 as u64) & target.cell_width.mask()),
+                };
+                writeln!(out, "{}{{", indent).unwrap();
+                writeln!(out, "{}    unsigned long n = {};", indent, iterations).unwrap();
+                for (offset, factor) in effects {
+                    writeln!(out, "{}    ptr[{}] += {}ul * n;", indent, offset, factor).unwrap();
+                }
+                writeln!(out, "{}    *ptr = 0;", indent).unwrap();
+                writeln!(out, "{}}}", indent).unwrap();
+            }
+            Stmt::Debug => writeln!(out, "{}{}", indent, debug_dump(target)).unwrap(),
+        }
+    }
+}
+
+/// Multiplicative inverse of the odd `value` modulo 2^64, by Newton's method:
+/// every iteration doubles the number of correct low bits.
+fn inverse_mod_2_64(value: u64) -> u64 {
+    let mut inverse = value;
+    for _ in 0..5 {
+        inverse = inverse.wrapping_mul(2u64.wrapping_sub(value.wrapping_mul(inverse)));
+    }
+    inverse
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    #[cfg(feature = "std")]
+    use super::super::bf2c::parse;
+    use super::super::bf2c::{parse_spanned, ParseOptions};
+    #[cfg(feature = "std")]
+    use super::super::compile::compile;
+    use super::super::dialect::Dialect;
+    #[cfg(feature = "std")]
+    use super::super::interp::run;
+    use super::super::localop::{IrPass, Prog, Stmt};
+    use super::super::error::Bf2cError;
+    #[cfg(feature = "std")]
+    use super::super::stream::tokenize;
+    use super::{inverse_mod_2_64, CellWidth, Limits, Transpiler};
+    #[cfg(feature = "std")]
+    use std::io;
+    #[cfg(feature = "std")]
+    use std::process::Command;
+    #[cfg(feature = "std")]
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn configures_the_tape() {
+        let code = Transpiler::builder().tape_size(30000).cell_width(CellWidth::U32).build().unwrap().transpile("+").unwrap();
+        assert!(code.starts_with("#include <stdint.h>\n#include <stdio.h>\n"));
+        assert!(code.contains("   uint32_t tape[30000];\n"));
+        assert!(code.contains("i < 30000; i++) tape[i] = 0;"));
+        assert!(Transpiler::builder().tape_size(0).build().is_err());
+    }
+
+    #[test]
+    fn optimized_output() {
+        let transpiler = Transpiler::builder().opt_level(1).build().unwrap();
+        let code = transpiler.transpile("+++>[-]<[->>+++<<]").unwrap();
+        assert!(code.contains("    *ptr += 3;\n    ptr += 1;\n    *ptr = 0;\n    ptr -= 1;\n"));
+        assert!(code.contains("        ptr[2] += 3ul * n;\n"));
+        let ook = Transpiler::builder().options(ParseOptions { dialect: Dialect::Ook, ..ParseOptions::default() });
+        assert_eq!(ook.build().unwrap().transpile("Ook. Ook.").unwrap(), Transpiler::default().transpile("+").unwrap());
+    }
+
+    #[test]
+    fn transpiles_token_streams() {
+        let transpiler = Transpiler::builder().opt_level(1).build().unwrap();
+        let tokens = parse_spanned("+[->+<]>.", &ParseOptions::default()).unwrap();
+        assert_eq!(transpiler.transpile_tokens(tokens.into_iter().map(Ok)).unwrap(), transpiler.transpile("+[->+<]>.").unwrap());
+        let limited = Transpiler::builder().limits(Limits { max_tokens: Some(2), ..Limits::default() }).build().unwrap();
+        // the limit stops the stream before the failing token
+        let tokens = parse_spanned("+++", &ParseOptions::default()).unwrap().into_iter().map(Ok).chain([Err(Bf2cError::Invalid("unread".to_string()))]);
+        assert!(matches!(limited.transpile_tokens(tokens), Err(Bf2cError::Limit(_))));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn transpiles_readers() {
+        let transpiler = Transpiler::default();
+        assert_eq!(transpiler.transpile_reader(&b"+[->+<]>."[..]).unwrap(), transpiler.transpile("+[->+<]>.").unwrap());
+        for source in ["+\n ]", "[\n[[]"] {
+            // checked by `transpile_tokens` as well as while tokenizing
+            let unverified = tokenize(source.as_bytes(), false, &ParseOptions::default()).unwrap();
+            for e in [transpiler.transpile_reader(source.as_bytes()).unwrap_err(), transpiler.transpile_tokens(unverified).unwrap_err()] {
+                assert!(matches!(e, Bf2cError::Parse(e) if e == parse(source, true).unwrap_err()));
+            }
+        }
+        let ook = Transpiler::builder().options(ParseOptions { dialect: Dialect::Ook, ..ParseOptions::default() }).build().unwrap();
+        assert!(matches!(ook.transpile_reader(&b"Ook. Ook."[..]), Err(Bf2cError::Invalid(_))));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn transpiles_mapped_files() {
+        let path = std::env::temp_dir().join(format!("bf-transpile-mmap-{}.bf", std::process::id()));
+        let transpiler = Transpiler::builder().opt_level(1).build().unwrap();
+        std::fs::write(&path, "+[->+<]\n>.").unwrap();
+        assert_eq!(transpiler.transpile_mapped(&path).unwrap(), transpiler.transpile("+[->+<]\n>.").unwrap());
+        std::fs::write(&path, "+]").unwrap();
+        assert!(matches!(transpiler.transpile_mapped(&path), Err(Bf2cError::Parse(_))));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(transpiler.transpile_mapped(&path), Err(Bf2cError::Io { .. })));
+    }
+
+    /// Turns every top-level `Add` into a zeroing of the cell.
+    #[derive(Debug)]
+    struct Clear;
+
+    impl IrPass for Clear {
+        fn name(&self) -> &str {
+            "clear"
+        }
+
+        fn run(&self, prog: &mut Prog) {
+            for stmt in prog.iter_mut().filter(|stmt| matches!(stmt, Stmt::Add(_))) {
+                *stmt = Stmt::ZeroLoop;
+            }
+        }
+    }
+
+    #[test]
+    fn runs_registered_passes() {
+        let builder = Transpiler::builder().pass(Clear);
+        assert!(builder.clone().opt_level(1).build().unwrap().transpile("+++.").unwrap().contains("    *ptr = 0;\n    putchar(*ptr);\n"));
+        assert!(builder.build().unwrap().transpile("+++.").unwrap().contains("(*ptr)++;"));
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let limited = |limits| Transpiler::builder().opt_level(1).limits(limits).build().unwrap().transpile("+[>[-]<-]");
+        assert!(limited(Limits { max_tokens: Some(9), max_ir_nodes: Some(6), ..Limits::default() }).is_ok());
+        let e = limited(Limits { max_tokens: Some(8), ..Limits::default() }).unwrap_err();
+        assert!(matches!(e, Bf2cError::Limit(message) if message == "token limit of 8 exceeded"));
+        assert!(matches!(limited(Limits { max_ir_nodes: Some(5), ..Limits::default() }), Err(Bf2cError::Limit(_))));
+        assert!(matches!(limited(Limits { max_output_bytes: Some(100), ..Limits::default() }), Err(Bf2cError::Limit(_))));
+    }
+
+    #[cfg(feature = "std")]
+    /// Collects the formatted log in memory.
+    #[derive(Clone, Default)]
+    struct Log(Arc<Mutex<Vec<u8>>>);
+
+    #[cfg(feature = "std")]
+    impl io::Write for Log {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn spans_around_each_phase() {
+        let log = Log::default();
+        let writer = log.clone();
+        let subscriber = tracing_subscriber::fmt().with_writer(move || writer.clone()).with_max_level(tracing::Level::DEBUG).without_time().with_ansi(false).finish();
+        let transpiler = Transpiler::builder().opt_level(1).pass(Clear).build().unwrap();
+        let code = tracing::subscriber::with_default(subscriber, || transpiler.transpile("+[-]").unwrap());
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        assert!(log.contains("transpile{bytes=4 opt_level=1}:parse{bytes=4 tokens=4}: "));
+        assert!(log.contains("transpile{bytes=4 opt_level=1}:optimize{tokens=4 statements=2}: "));
+        assert!(log.contains("transpile{bytes=4 opt_level=1}:pass{name=\"clear\" statements=2}: "));
+        assert!(log.contains(&format!("transpile{{bytes=4 opt_level=1}}:emit{{tokens=4 bytes={}}}: ", code.len())));
+    }
+
+    #[test]
+    fn inverses() {
+        for value in [1u64, 3, 5, 255, 0xffff_ffff_ffff_ffff] {
+            assert_eq!(value.wrapping_mul(inverse_mod_2_64(value)), 1);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn optimized_code_matches_the_interpreter() {
+        if Command::new("cc").arg("--version").output().is_err() {
+            return; // no C compiler available
+        }
+        // a decrement of 3 needs the inverse, the scan and the nested loop stay loops
+        let source = "++++[>+++<-]>[--->++<]>>+>+[<]>[.>]++++++[<++++++++>-]<.";
+        let expected = run(source, b"").unwrap().stdout;
+        let dir = std::env::temp_dir().join(format!("bf-transpiler-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let outputs: Vec<Vec<u8>> = (0..2)
+            .map(|level| {
+                let code = Transpiler::builder().opt_level(level).build().unwrap().transpile(source).unwrap();
+                Command::new(compile(&code, "cc", &dir).unwrap()).output().unwrap().stdout
+            })
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(outputs, vec![expected.clone(), expected]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn wide_cells_match_without_optimization() {
+        if Command::new("cc").arg("--version").output().is_err() {
+            return; // no C compiler available
+        }
+        // a control cell counting up, and a decrement of 257, which is 1 modulo 256
+        let source = format!("-[+>+<]>.>{}[{}>+<]>.", "+".repeat(514), "-".repeat(257));
+        let dir = std::env::temp_dir().join(format!("bf-wide-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for width in [CellWidth::U16, CellWidth::U32] {
+            let outputs: Vec<Vec<u8>> = (0..2)
+                .map(|level| {
+                    let code = Transpiler::builder().cell_width(width).opt_level(level).build().unwrap().transpile(&source).unwrap();
+                    Command::new(compile(&code, "cc", &dir).unwrap()).output().unwrap().stdout
+                })
+                .collect();
+            assert_eq!(outputs, vec![vec![1, 2], vec![1, 2]], "{:?}", width);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
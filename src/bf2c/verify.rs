@@ -0,0 +1,139 @@
+//! Differential check between the interpreter and the generated C code.
+//!
+//! The same program is run by the interpreter, one instruction at a time,
+//! and compiled with a C compiler, and both outputs must be identical for the
+//! same input. A difference means either the optimizer or the emitter
+//! miscompiled the program.
+//!
+//! The interpreter has 8-bit cells. Code generated for wider cells can only
+//! be checked against it if no cell ever wraps around, and no input is read
+//! past its end, since both give a different value in a wider cell.
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+use super::compile::compile;
+use super::error::Bf2cError;
+use super::interp::{Limits, Machine};
+use super::transpiler::{CellWidth, Transpiler};
+
+/// Outputs of the two backends for one input.
+pub struct Comparison {
+    pub interpreter: Vec<u8>,
+    pub compiled: Vec<u8>,
+}
+
+impl Comparison {
+    /// Index of the first byte where the outputs differ, or the length of the
+    /// shorter one if it is a prefix of the other.
+    pub fn first_difference(&self) -> Option<usize> {
+        let common = self.interpreter.iter().zip(&self.compiled).position(|(a, b)| a != b);
+        match common {
+            Some(index) => Some(index),
+            None if self.interpreter.len() != self.compiled.len() => {
+                Some(self.interpreter.len().min(self.compiled.len()))
+            }
+            None => None,
+        }
+    }
+}
+
+/// Runs `source` on `input` with the unoptimized interpreter and as C from
+/// `transpiler` compiled by `cc`, building in `work_dir`. `limits` only apply
+/// to the interpreter.
+///
+/// Returns `None` without compiling anything if `transpiler` has cells wider
+/// than 8 bits and the output may depend on the interpreter's cells wrapping.
+pub fn verify_backend(
+    source: &str,
+    input: &[u8],
+    cc: &str,
+    work_dir: &Path,
+    limits: &Limits,
+    transpiler: &Transpiler,
+) -> Result<Option<Comparison>, Bf2cError> {
+    let (interpreter, wraps) = interpret(source, input, limits, transpiler)?;
+    if wraps && transpiler.cell_width() != CellWidth::U8 {
+        return Ok(None);
+    }
+
+    let code = transpiler.transpile(source)?;
+    let executable = compile(&code, cc, work_dir)?;
+
+    let mut child = Command::new(&executable)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| Bf2cError::Compile(format!("cannot run compiled program: {}", e)))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // a program that stops reading early closes the pipe, which is fine
+    let _ = stdin.write_all(input);
+    drop(stdin);
+    let result = child.wait_with_output().map_err(|e| Bf2cError::Compile(format!("compiled program failed: {}", e)))?;
+    if !result.status.success() {
+        return Err(Bf2cError::Compile(format!("compiled program exited with {}", result.status)));
+    }
+    Ok(Some(Comparison { interpreter, compiled: result.stdout }))
+}
+
+/// Output of the interpreter stepping through the raw instructions, and
+/// whether a cell wrapped around or took the end of input on the way.
+fn interpret(source: &str, input: &[u8], limits: &Limits, transpiler: &Transpiler) -> Result<(Vec<u8>, bool), Bf2cError> {
+    let mut machine = Machine::from_source(source, false, transpiler.options())?;
+    let deadline = limits.timeout.map(|timeout| Instant::now() + timeout);
+    let (mut input, mut output, mut wraps) = (input, Vec::new(), false);
+    while !machine.is_finished() {
+        machine.check_limits(limits, deadline)?;
+        let ptr = machine.tape.ptr;
+        let before = machine.tape.cells[ptr];
+        machine.step(&mut input, &mut output)?;
+        // also catches reading a 0 or a 255, which is harmless but rare
+        let after = machine.tape.cells[ptr];
+        wraps |= (before == 255 && after == 0) || (after == 255 && before != 254);
+    }
+    Ok((output, wraps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::interp::Limits;
+    use super::super::transpiler::{CellWidth, Transpiler};
+    use super::{verify_backend, Comparison};
+    use std::process::Command;
+
+    #[test]
+    fn first_difference() {
+        let compare = |a: &[u8], b: &[u8]| Comparison { interpreter: a.to_vec(), compiled: b.to_vec() }.first_difference();
+        assert_eq!(compare(b"abc", b"abc"), None);
+        assert_eq!(compare(b"abc", b"abd"), Some(2));
+        assert_eq!(compare(b"ab", b"abc"), Some(2));
+    }
+
+    #[test]
+    fn backends_agree() {
+        if Command::new("cc").arg("--version").output().is_err() {
+            return; // no C compiler to check against
+        }
+        let dir = std::env::temp_dir().join(format!("bf-verify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.,[.,]";
+        let limits = Limits { max_steps: Some(100_000), timeout: None };
+        let verify = |source: &str, width, level| {
+            let transpiler = Transpiler::builder().cell_width(width).opt_level(level).build().unwrap();
+            verify_backend(source, b"echo\0", "cc", &dir, &limits, &transpiler).unwrap()
+        };
+        for width in [CellWidth::U8, CellWidth::U16, CellWidth::U32] {
+            for level in 0..2 {
+                let comparison = verify(source, width, level).unwrap();
+                assert_eq!(comparison.interpreter, b"Hello World!\necho");
+                assert_eq!(comparison.first_difference(), None, "{:?} at -O{}", width, level);
+            }
+        }
+        // 255 after wrapping below zero, which wider cells do not give
+        assert!(verify("-.", CellWidth::U8, 1).is_some());
+        assert!(verify("-.", CellWidth::U16, 1).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
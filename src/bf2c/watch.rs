@@ -0,0 +1,68 @@
+//! Watchpoints on tape cells, checked by the interpreter's `Machine`.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Accesses to a cell that trigger a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    pub fn on_read(self) -> bool {
+        matches!(self, WatchKind::Read | WatchKind::ReadWrite)
+    }
+
+    pub fn on_write(self) -> bool {
+        matches!(self, WatchKind::Write | WatchKind::ReadWrite)
+    }
+}
+
+impl FromStr for WatchKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "r" | "read" => Ok(WatchKind::Read),
+            "w" | "write" => Ok(WatchKind::Write),
+            "rw" | "access" => Ok(WatchKind::ReadWrite),
+            _ => Err(format!("unknown watch kind '{}', expected r, w or rw", s)),
+        }
+    }
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::ReadWrite => "read/write",
+        })
+    }
+}
+
+/// A watched cell accessed by the last step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub cell: usize,
+    /// Whether the cell was written rather than only read.
+    pub write: bool,
+    /// Source offset of the step that accessed the cell.
+    pub offset: usize,
+    /// Value of the cell before and after the step.
+    pub old: u8,
+    pub new: u8,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.write {
+            write!(f, "cell {} written at offset {}: {} -> {}", self.cell, self.offset, self.old, self.new)
+        } else {
+            write!(f, "cell {} read at offset {}: {}", self.cell, self.offset, self.old)
+        }
+    }
+}
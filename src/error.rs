@@ -0,0 +1,136 @@
+//! Errors reported by the command line tool, and the exit codes scripts can
+//! rely on to tell them apart. Every error maps to one of the codes below;
+//! only `--run` exits with another code, the compiled program's own, and only
+//! once the program was built.
+
+use cbt_fuck::bf2c::error::Bf2cError;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// The command line could not be parsed.
+pub const EXIT_USAGE: i32 = 1;
+/// The Brainfuck source, a mapping table, a trace or a snapshot is
+/// malformed, or a check found a problem: `--check` reported errors, or
+/// `verify-backend` found the interpreter and the generated C disagreeing.
+pub const EXIT_PARSE: i32 = 2;
+/// A file or stream could not be read or written, the C compiler or the
+/// compiled program failed, or the tool itself did, by panicking or by
+/// reaching a state it considers a bug.
+pub const EXIT_IO: i32 = 3;
+/// A step, time or output size limit was exceeded, or an interpreted program
+/// moved the data pointer off the tape.
+pub const EXIT_LIMIT: i32 = 4;
+
+#[derive(Debug)]
+pub enum CliError {
+    Usage(String),
+    Io(String),
+    Parse(String),
+    /// A check of a well-formed program failed.
+    Check(String),
+    Limit(String),
+    Program(String),
+    Internal(String),
+}
+
+impl CliError {
+    /// I/O error on `path`, with the common causes spelled out.
+    pub fn io(action: &str, path: &Path, e: io::Error) -> Self {
+        let reason = match e.kind() {
+            io::ErrorKind::NotFound => "no such file or directory".to_string(),
+            io::ErrorKind::PermissionDenied => "permission denied".to_string(),
+            _ => e.to_string(),
+        };
+        CliError::Io(format!("cannot {} '{}': {}", action, path.display(), reason))
+    }
+
+    /// Error standing for several `errors`, of the same kind as the first.
+    pub fn several(errors: &[CliError], summary: String) -> Self {
+        match errors.first() {
+            Some(CliError::Usage(_)) => CliError::Usage(summary),
+            Some(CliError::Io(_)) => CliError::Io(summary),
+            Some(CliError::Parse(_)) => CliError::Parse(summary),
+            Some(CliError::Check(_)) => CliError::Check(summary),
+            Some(CliError::Limit(_)) => CliError::Limit(summary),
+            Some(CliError::Program(_)) => CliError::Program(summary),
+            Some(CliError::Internal(_)) | None => CliError::Internal(summary),
+        }
+    }
+
+    /// Error writing to stdout.
+    pub fn stdout(e: io::Error) -> Self {
+        CliError::Io(format!("cannot write to stdout: {}", e))
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => EXIT_USAGE,
+            CliError::Parse(_) | CliError::Check(_) => EXIT_PARSE,
+            CliError::Io(_) | CliError::Internal(_) => EXIT_IO,
+            // a program fails by leaving the tape, which is a limit like any other
+            CliError::Limit(_) | CliError::Program(_) => EXIT_LIMIT,
+        }
+    }
+}
+
+/// Library errors, by what went wrong.
+impl From<Bf2cError> for CliError {
+    fn from(e: Bf2cError) -> Self {
+        match e {
+            Bf2cError::Parse(_) | Bf2cError::Invalid(_) => CliError::Parse(e.to_string()),
+            Bf2cError::Runtime(_) => CliError::Program(e.to_string()),
+            Bf2cError::Limit(message) => CliError::Limit(message),
+            Bf2cError::Compile(_) | Bf2cError::Io { .. } => CliError::Io(e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Usage(message)
+            | CliError::Io(message)
+            | CliError::Parse(message)
+            | CliError::Check(message)
+            | CliError::Limit(message)
+            | CliError::Internal(message) => f.write_str(message),
+            CliError::Program(message) => write!(f, "program failed: {}", message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CliError, EXIT_IO, EXIT_LIMIT, EXIT_PARSE};
+    use cbt_fuck::bf2c::error::Bf2cError;
+    use std::io;
+    use std::path::Path;
+
+    #[test]
+    fn io_errors_name_the_file() {
+        let e = CliError::io("read", Path::new("x.bf"), io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(e.to_string(), "cannot read 'x.bf': no such file or directory");
+        assert_eq!(e.exit_code(), EXIT_IO);
+        assert_eq!(CliError::Parse("bad".to_string()).exit_code(), EXIT_PARSE);
+        assert_eq!(CliError::Check("the backends disagree".to_string()).exit_code(), EXIT_PARSE);
+        assert_eq!(CliError::Internal("bug".to_string()).exit_code(), EXIT_IO);
+    }
+
+    #[test]
+    fn limits_are_told_apart_from_failures() {
+        let limit = Bf2cError::Limit("step limit of 10 exceeded at offset 4".to_string());
+        assert_eq!(CliError::from(limit).exit_code(), EXIT_LIMIT);
+        let failure = Bf2cError::Runtime("data pointer moved out of the tape (cell 0)".to_string());
+        assert_eq!(CliError::from(failure).exit_code(), EXIT_LIMIT);
+        let compiler = Bf2cError::Compile("cc failed to compile the generated code (exit status: 1)".to_string());
+        assert_eq!(CliError::from(compiler).exit_code(), EXIT_IO);
+    }
+
+    #[test]
+    fn several_errors_keep_the_first_kind() {
+        let errors = [CliError::Parse("a".to_string()), CliError::Io("b".to_string())];
+        let summary = CliError::several(&errors, "2 of 3 inputs failed".to_string());
+        assert_eq!((summary.exit_code(), summary.to_string()), (EXIT_PARSE, "2 of 3 inputs failed".to_string()));
+    }
+}
@@ -1,13 +1,1090 @@
-mod bf2c;
-use bf2c::bf2c::bf2cify;
+use cbt_fuck::bf2c::bf2c::{parse_spanned, parse_with_options, ParseError, ParseOptions};
+use cbt_fuck::bf2c::check::{check, line_column, snippet, Severity};
+use cbt_fuck::bf2c::compile::{build, compile};
+#[cfg(feature = "dap")]
+use cbt_fuck::bf2c::dap;
+use cbt_fuck::bf2c::debugger::{Debugger, DEFAULT_JOURNAL_LEN};
+use cbt_fuck::bf2c::dialect::Dialect;
+use cbt_fuck::bf2c::dot::to_dot;
+use cbt_fuck::bf2c::error::Bf2cError;
+use cbt_fuck::bf2c::include::{Expanded, IncludeError};
+use cbt_fuck::bf2c::interp::{run_prog, run_symbols, Limits, Machine, Tape, TAPE_SIZE};
+#[cfg(feature = "jit")]
+use cbt_fuck::bf2c::jit::run_jit;
+#[cfg(feature = "lsp")]
+use cbt_fuck::bf2c::lsp;
+use cbt_fuck::bf2c::localop::{optimize, optimize_with_spans};
+#[cfg(feature = "jit")]
+use cbt_fuck::bf2c::localop::Prog;
+use cbt_fuck::bf2c::profile::{profile, Profile};
+use cbt_fuck::bf2c::repl::repl;
+use cbt_fuck::bf2c::report::report;
+use cbt_fuck::bf2c::snapshot::Snapshot;
+use cbt_fuck::bf2c::trace::{compare_io, read_trace, Divergence, Event, TraceFilter, Tracer};
+use cbt_fuck::bf2c::transpiler::{CellWidth, Transpiler};
+use cbt_fuck::bf2c::verify::verify_backend;
+use clap::{ArgAction, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use error::CliError;
+use rayon::prelude::*;
 use std::fs;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, Level};
+
+mod error;
+
+/// Transpile Brainfuck to C, or run and debug it with the built-in interpreter
+#[derive(Parser)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    after_help = "Exit codes: 0 success, 1 usage error, 2 malformed input or failed check, 3 I/O or tool failure, \
+                  4 limit exceeded. 2 covers sources, mapping tables, traces and snapshots that do not parse, errors \
+                  found by --check, and verify-backend finding the backends disagree. 3 covers files that cannot be \
+                  read or written, failures of the C compiler and of the compiled program, and crashes of this tool. \
+                  4 covers step, time and output size limits, and an interpreted program moving off the tape. \
+                  Once the program is built, --run exits with its exit code instead"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Brainfuck sources to transpile, '-' for stdin. Several sources are
+    /// concatenated into one program unless --out-dir is given
+    #[arg(required = true, value_name = "INPUT")]
+    inputs: Vec<PathBuf>,
+
+    /// Where to write the generated C, '-' for stdout (the default)
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Transpile every input separately into DIR/<input name>.c
+    #[arg(long, value_name = "DIR", conflicts_with = "output")]
+    out_dir: Option<PathBuf>,
+
+    /// Only report problems in the inputs, without writing any output
+    #[arg(long, conflicts_with_all = ["output", "out_dir"])]
+    check: bool,
+
+    /// Compile the generated C and run it, exiting with the program's exit code
+    #[arg(long, conflicts_with_all = ["output", "out_dir", "check"])]
+    run: bool,
+
+    /// C compiler used by --run
+    #[arg(long, default_value = "cc", requires = "run")]
+    cc: String,
+
+    /// Fail instead of writing generated C larger than this many bytes
+    #[arg(long, value_name = "BYTES", conflicts_with = "check")]
+    max_output_bytes: Option<usize>,
+
+    /// What to write: C, or a Graphviz graph of the loops of the optimized IR
+    #[arg(long, value_enum, default_value = "c", conflicts_with_all = ["check", "run"])]
+    emit: Emit,
+
+    /// Also write an HTML page explaining how the optimizer treated every loop
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["out_dir", "check", "run"])]
+    report: Option<PathBuf>,
+
+    #[command(flatten)]
+    language: Language,
+
+    #[command(flatten)]
+    codegen: Codegen,
+
+    /// Log progress and timings of each stage to stderr, -vv and -vvv for more detail
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Only log errors, hiding warnings
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+}
+
+impl Cli {
+    fn log_level(&self) -> Level {
+        match (self.quiet, self.verbose) {
+            (true, _) => Level::ERROR,
+            (false, 0) => Level::WARN,
+            (false, 1) => Level::INFO,
+            (false, 2) => Level::DEBUG,
+            _ => Level::TRACE,
+        }
+    }
+}
+
+/// Number of loops and statements listed by `run --profile`.
+const PROFILE_REPORT_LEN: usize = 10;
+
+#[derive(Subcommand)]
+enum Command {
+    /// Execute a Brainfuck program with the built-in interpreter
+    Run {
+        /// Brainfuck source to run, '-' for stdin
+        input: PathBuf,
+
+        /// Report the hottest loops and statements on stderr after the run
+        #[arg(long)]
+        profile: bool,
+
+        /// Write an HTML page explaining the optimized loops, with profiling data from the run
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["no_optimize", "dump_tape", "resume", "trace", "heatmap"])]
+        report: Option<PathBuf>,
+
+        /// Execute raw instructions instead of the optimized IR
+        #[arg(long, conflicts_with = "profile")]
+        no_optimize: bool,
+
+        /// Compile the optimized IR to machine code and run that, without a C compiler
+        #[cfg(feature = "jit")]
+        #[arg(long, conflicts_with_all = ["profile", "report", "no_optimize", "max_steps", "timeout", "dump_tape", "resume", "trace", "heatmap"])]
+        jit: bool,
+
+        /// Abort after executing this many steps
+        #[arg(long, value_name = "STEPS")]
+        max_steps: Option<u64>,
+
+        /// Abort after running for this many seconds
+        #[arg(long, value_name = "SECONDS")]
+        timeout: Option<f64>,
+
+        /// Save the tape to this file when the run stops
+        #[arg(long, value_name = "FILE", conflicts_with = "profile")]
+        dump_tape: Option<PathBuf>,
+
+        /// Continue from a tape snapshot
+        #[arg(long, value_name = "FILE", conflicts_with = "profile")]
+        resume: Option<PathBuf>,
+
+        /// Record executed instructions to this file
+        #[arg(long, value_name = "FILE", conflicts_with = "profile")]
+        trace: Option<PathBuf>,
+
+        /// Save a heatmap of tape activity to this file, as HTML if it ends in .html
+        #[arg(long, value_name = "FILE", conflicts_with = "profile")]
+        heatmap: Option<PathBuf>,
+
+        /// Events to record with --trace: all, loops (loops and I/O) or io
+        #[arg(long, value_name = "FILTER", default_value = "all", requires = "trace")]
+        trace_filter: TraceFilter,
+
+        #[command(flatten)]
+        language: Language,
+
+        #[command(flatten)]
+        io: ProgramIo,
+    },
+    /// Step through a Brainfuck program interactively
+    Debug {
+        /// Brainfuck source to debug
+        input: PathBuf,
+
+        /// Step through optimized IR statements instead of single instructions
+        #[arg(long)]
+        ir: bool,
+
+        /// Number of steps that can be undone with `back`, 0 to disable
+        #[arg(long, value_name = "STEPS", default_value_t = DEFAULT_JOURNAL_LEN)]
+        journal_len: usize,
+
+        /// Continue from a tape snapshot
+        #[arg(long, value_name = "FILE")]
+        resume: Option<PathBuf>,
+
+        #[command(flatten)]
+        language: Language,
+
+        #[command(flatten)]
+        io: ProgramIo,
+    },
+    /// Check that the interpreter and the generated C code, at every optimization level and cell width, produce the same output
+    VerifyBackend {
+        /// Brainfuck source to check, '-' for stdin
+        input: PathBuf,
+
+        /// C compiler used to build the generated code
+        #[arg(long, default_value = "cc")]
+        cc: String,
+
+        /// Abort the interpreter after executing this many steps
+        #[arg(long, value_name = "STEPS")]
+        max_steps: Option<u64>,
+
+        /// Read program input from this file
+        #[arg(long, value_name = "FILE", conflicts_with = "input_string")]
+        input_file: Option<PathBuf>,
+
+        /// Use this text as program input
+        #[arg(long, value_name = "TEXT")]
+        input_string: Option<String>,
+
+        #[command(flatten)]
+        language: Language,
+    },
+    /// Transpile Brainfuck sources and compile the C into an executable
+    Build {
+        /// Brainfuck sources, concatenated into one program, '-' for stdin
+        #[arg(required = true, value_name = "INPUT")]
+        inputs: Vec<PathBuf>,
+
+        /// Executable to write, by default the first input's name without its extension
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// C compiler to build the generated code with
+        #[arg(long, default_value = "cc")]
+        cc: String,
+
+        /// Argument for the C compiler, such as -O2; repeat for several
+        #[arg(long = "cflag", value_name = "FLAG", allow_hyphen_values = true)]
+        cflags: Vec<String>,
+
+        #[command(flatten)]
+        language: Language,
+
+        #[command(flatten)]
+        codegen: Codegen,
+    },
+    /// Show the tokens, optimized IR and generated C of snippets typed at a prompt
+    Repl {
+        #[command(flatten)]
+        language: Language,
+    },
+    /// Serve the Debug Adapter Protocol on stdin and stdout, for editors
+    Dap {
+        #[command(flatten)]
+        language: Language,
+    },
+    /// Serve the Language Server Protocol on stdin and stdout, for editors
+    Lsp {
+        #[command(flatten)]
+        language: Language,
+    },
+    /// Show a trace recorded by `run --trace`, or compare two traces
+    Trace {
+        /// Trace file to inspect
+        file: PathBuf,
+
+        /// Brainfuck source the trace was recorded from, to show its instructions
+        #[arg(long, value_name = "FILE")]
+        source: Option<PathBuf>,
+
+        /// Find the first I/O event where this trace disagrees with FILE
+        #[arg(long, value_name = "OTHER")]
+        diff: Option<PathBuf>,
+    },
+}
+
+/// Language extensions accepted by every mode.
+#[derive(Args)]
+struct Language {
+    /// Treat '#' as an instruction that dumps the tape around the pointer to stderr
+    #[arg(long)]
+    debug_hash: bool,
+
+    /// Brainfuck-isomorphic language the sources are written in
+    #[arg(long, value_enum, default_value = "bf")]
+    dialect: DialectName,
+
+    /// Mapping table for --dialect custom, one '<instruction> <token>' pair per line
+    #[arg(long, value_name = "FILE", required_if_eq("dialect", "custom"))]
+    mapping: Option<PathBuf>,
+
+    /// Treat the sources as prose with code in ``` fenced blocks and on lines starting with '>'
+    #[arg(long)]
+    literate: bool,
+
+    /// Replace '@include "file"' lines with the file, relative to the including one
+    #[arg(long)]
+    includes: bool,
+
+    /// Map the source into memory when transpiling it to C, for a single plain Brainfuck file
+    #[cfg(feature = "mmap")]
+    #[arg(long, conflicts_with_all = ["includes", "literate"])]
+    mmap: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DialectName {
+    Bf,
+    Ook,
+    Blub,
+    Custom,
+}
+
+impl Language {
+    fn options(&self) -> Result<ParseOptions, CliError> {
+        let dialect = match self.dialect {
+            DialectName::Bf => Dialect::Brainfuck,
+            DialectName::Ook => Dialect::Ook,
+            DialectName::Blub => Dialect::Blub,
+            DialectName::Custom => {
+                let path = self.mapping.as_deref().expect("clap requires --mapping for custom dialects");
+                let table = fs::read_to_string(path).map_err(|e| CliError::io("read --mapping", path, e))?;
+                Dialect::custom(&table).map_err(|e| parse_error(path, e))?
+            }
+        };
+        Ok(ParseOptions { debug: self.debug_hash, dialect, literate: self.literate })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Emit {
+    C,
+    Dot,
+}
+
+impl Emit {
+    /// Extension of the files written to --out-dir.
+    fn extension(self) -> &'static str {
+        match self {
+            Emit::C => "c",
+            Emit::Dot => "dot",
+        }
+    }
+}
+
+/// Layout and optimization of the generated C.
+#[derive(Args)]
+struct Codegen {
+    /// Number of cells on the tape of the generated program
+    #[arg(long, value_name = "CELLS", default_value_t = TAPE_SIZE as u64, value_parser = clap::value_parser!(u64).range(1..))]
+    tape_size: u64,
+
+    /// Bits per cell of the generated program. Only 8 behaves like the interpreter behind run, debug and the editor tools
+    #[arg(long, value_name = "BITS", default_value = "8")]
+    cell_width: CellBits,
+
+    /// 0 emits one statement per instruction, 1 coalesces runs of instructions and simplifies loops
+    #[arg(short = 'O', long, value_name = "LEVEL", default_value_t = 0)]
+    opt_level: u8,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CellBits {
+    #[value(name = "8")]
+    U8,
+    #[value(name = "16")]
+    U16,
+    #[value(name = "32")]
+    U32,
+}
+
+impl Codegen {
+    fn transpiler(&self, language: &Language) -> Result<Transpiler, CliError> {
+        let cell_width = match self.cell_width {
+            CellBits::U8 => CellWidth::U8,
+            CellBits::U16 => CellWidth::U16,
+            CellBits::U32 => CellWidth::U32,
+        };
+        let builder = Transpiler::builder()
+            .options(language.options()?)
+            .tape_size(self.tape_size as usize)
+            .cell_width(cell_width)
+            .opt_level(self.opt_level);
+        Ok(builder.build()?)
+    }
+}
+
+/// Where an interpreted program reads its input and writes its output.
+#[derive(Args)]
+struct ProgramIo {
+    /// Read program input from this file
+    #[arg(long, value_name = "FILE", conflicts_with = "input_string")]
+    input_file: Option<PathBuf>,
+
+    /// Use this text as program input
+    #[arg(long, value_name = "TEXT")]
+    input_string: Option<String>,
+
+    /// Write program output to this file
+    #[arg(long, value_name = "FILE")]
+    output_file: Option<PathBuf>,
+}
+
+impl ProgramIo {
+    fn reader(&self) -> Result<Option<Box<dyn Read>>, CliError> {
+        if let Some(path) = &self.input_file {
+            let file = File::open(path).map_err(|e| CliError::io("read --input-file", path, e))?;
+            return Ok(Some(Box::new(file)));
+        }
+        Ok(self.input_string.clone().map(|text| Box::new(io::Cursor::new(text.into_bytes())) as Box<dyn Read>))
+    }
+
+    fn writer(&self) -> Result<Option<Box<dyn Write>>, CliError> {
+        let Some(path) = &self.output_file else {
+            return Ok(None);
+        };
+        let file = File::create(path).map_err(|e| CliError::io("create --output-file", path, e))?;
+        Ok(Some(Box::new(BufWriter::new(file))))
+    }
+}
+
+/// Output of `--version`: what a bug report needs to identify the binary.
+static LONG_VERSION: LazyLock<String> = LazyLock::new(|| {
+    format!(
+        "{}\ncommit: {}\nfeatures: {}\ntape: {} cells\nrun limits: no step limit, no timeout\ndebugger journal: {} steps",
+        env!("CARGO_PKG_VERSION"),
+        env!("CBT_GIT_COMMIT"),
+        env!("CBT_FEATURES"),
+        TAPE_SIZE,
+        DEFAULT_JOURNAL_LEN
+    )
+});
 
 fn main() {
-    println!("Hello, world!");
-    let contents = fs::read_to_string("src/bf.bf").expect("Unable to read file");
-    let result = bf2cify(contents).expect("failed to bf2cify");
-    let mut file = File::create("c.c").unwrap();
-    file.write_all(result.as_ref()).unwrap();
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        eprintln!("error: internal error, please report this as a bug");
+        // a crash is a failure of the tool itself, see `EXIT_IO`
+        std::process::exit(error::EXIT_IO);
+    }));
+    let cli = Cli::command()
+        .long_version(LONG_VERSION.as_str())
+        .try_get_matches()
+        .and_then(|matches| Cli::from_arg_matches(&matches))
+        .unwrap_or_else(|e| {
+            if !e.use_stderr() {
+                // --help and --version
+                e.exit();
+            }
+            let _ = e.print();
+            std::process::exit(error::EXIT_USAGE);
+        });
+    tracing_subscriber::fmt().with_writer(io::stderr).with_max_level(cli.log_level()).without_time().init();
+    let result = match cli.command {
+        Some(Command::Run {
+            input,
+            profile: with_profile,
+            report,
+            no_optimize,
+            #[cfg(feature = "jit")]
+            jit,
+            max_steps,
+            timeout,
+            dump_tape,
+            resume,
+            trace,
+            trace_filter,
+            heatmap,
+            language,
+            io: program_io,
+        }) => {
+            let limits = Limits { max_steps, timeout: timeout.map(Duration::from_secs_f64) };
+            let run = RunArgs {
+                with_profile,
+                report,
+                no_optimize,
+                #[cfg(feature = "jit")]
+                jit,
+                limits,
+                dump_tape,
+                resume,
+                trace,
+                trace_filter,
+                heatmap,
+            };
+            run_program(&input, run, &language, &program_io)
+        }
+        Some(Command::Debug { input, ir, journal_len, resume, language, io: program_io }) => {
+            debug(&input, ir, journal_len, resume.as_deref(), &language, &program_io)
+        }
+        Some(Command::VerifyBackend { input, cc, max_steps, input_file, input_string, language }) => {
+            verify(&input, &cc, max_steps, input_file.as_deref(), input_string, &language)
+        }
+        Some(Command::Build { inputs, output, cc, cflags, language, codegen }) => codegen
+            .transpiler(&language)
+            .and_then(|transpiler| build_executable(&inputs, output.as_deref(), &cc, &cflags, &language, &transpiler)),
+        Some(Command::Repl { language }) => language.options().and_then(|options| {
+            repl(&mut io::stdin().lock(), &mut io::stdout(), &options).map_err(CliError::from)
+        }),
+        Some(Command::Dap { language }) => language.options().and_then(serve_dap),
+        Some(Command::Lsp { language }) => language.options().and_then(serve_lsp),
+        Some(Command::Trace { file, source, diff }) => inspect_trace(&file, source.as_deref(), diff.as_deref()),
+        None if cli.check => check_sources(&cli.inputs, &cli.language),
+        None if cli.run => match cli
+            .codegen
+            .transpiler(&cli.language)
+            .and_then(|transpiler| compile_and_run(&cli.inputs, &cli.cc, cli.max_output_bytes, &cli.language, &transpiler))
+        {
+            Ok(code) => std::process::exit(code),
+            Err(e) => Err(e),
+        },
+        None => cli.codegen.transpiler(&cli.language).and_then(|transpiler| match cli.out_dir.as_deref() {
+            Some(out_dir) => transpile_each(&cli.inputs, out_dir, cli.max_output_bytes, cli.emit, &cli.language, &transpiler),
+            None => {
+                let (output, report) = (cli.output.as_deref(), cli.report.as_deref());
+                transpile(&cli.inputs, output, report, cli.max_output_bytes, cli.emit, &cli.language, &transpiler)
+            }
+        }),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// Options of the `run` subcommand that are not about the program itself.
+struct RunArgs {
+    with_profile: bool,
+    report: Option<PathBuf>,
+    no_optimize: bool,
+    #[cfg(feature = "jit")]
+    jit: bool,
+    limits: Limits,
+    dump_tape: Option<PathBuf>,
+    resume: Option<PathBuf>,
+    trace: Option<PathBuf>,
+    trace_filter: TraceFilter,
+    heatmap: Option<PathBuf>,
+}
+
+fn run_program(input: &Path, run: RunArgs, language: &Language, program_io: &ProgramIo) -> Result<(), CliError> {
+    let options = language.options()?;
+    let program = read_program(input, language)?;
+    let contents = &program.text;
+    let (tokens, _) = parse_with_options(contents, true, &options).map_err(|e| bracket_error(&program, e))?;
+    let mut stdin = program_io.reader()?.unwrap_or_else(|| Box::new(io::stdin().lock()));
+    let mut stdout = program_io.writer()?.unwrap_or_else(|| Box::new(io::stdout().lock()));
+    // only the stepping machine can check limits, report offsets and record activity
+    let stepping = run.limits.max_steps.is_some()
+        || run.limits.timeout.is_some()
+        || run.dump_tape.is_some()
+        || run.resume.is_some()
+        || run.trace.is_some()
+        || run.heatmap.is_some();
+    if run.with_profile || run.report.is_some() {
+        let result = profile(contents, &mut stdin, &mut stdout, &run.limits, &options)?;
+        if run.with_profile {
+            eprint!("{}", result.report(contents, PROFILE_REPORT_LEN));
+        }
+        if let Some(path) = &run.report {
+            write_report(path, &program, &options, Some(&result))?;
+        }
+        return Ok(());
+    }
+    #[cfg(feature = "jit")]
+    if run.jit {
+        return run_compiled(&optimize(&tokens), &mut stdin, &mut stdout);
+    }
+    if !stepping {
+        let start = Instant::now();
+        let result = if run.no_optimize {
+            run_symbols(&tokens, &mut Tape::new(), &mut stdin, &mut stdout)
+        } else {
+            run_prog(&optimize(&tokens), &mut Tape::new(), &mut stdin, &mut stdout)
+        };
+        info!(elapsed = ?start.elapsed(), "program finished");
+        return Ok(result?);
+    }
+
+    // the source was parsed above, so failing now is a bug
+    let mut machine = Machine::from_source(contents, !run.no_optimize, &options).map_err(|e| CliError::Internal(e.to_string()))?;
+    if let Some(path) = &run.resume {
+        machine.restore(read_snapshot(path)?)?;
+    }
+    if let Some(path) = &run.trace {
+        let file = File::create(path).map_err(|e| CliError::io("create trace file", path, e))?;
+        machine.set_tracer(Tracer::new(Box::new(BufWriter::new(file)), run.trace_filter));
+    }
+    if run.heatmap.is_some() {
+        machine.set_activity();
+    }
+    let start = Instant::now();
+    let result = machine.run(&mut stdin, &mut stdout, &run.limits);
+    info!(steps = machine.steps, elapsed = ?start.elapsed(), "program finished");
+    // save what is known about the run even if it failed
+    if let Some(path) = &run.dump_tape {
+        let mut file = File::create(path).map_err(|e| CliError::io("create snapshot file", path, e))?;
+        machine.snapshot().write(&mut file)?;
+    }
+    if let (Some(path), Some(activity)) = (&run.heatmap, machine.activity()) {
+        fs::write(path, activity.render_for(path, &machine.tape)).map_err(|e| CliError::io("write heatmap", path, e))?;
+    }
+    Ok(result?)
+}
+
+/// Runs `prog` compiled to machine code.
+#[cfg(feature = "jit")]
+fn run_compiled(prog: &Prog, stdin: &mut impl Read, stdout: &mut impl Write) -> Result<(), CliError> {
+    let start = Instant::now();
+    let result = run_jit(prog, &mut Tape::new(), stdin, stdout);
+    info!(elapsed = ?start.elapsed(), "program finished");
+    Ok(result?)
+}
+
+#[cfg(feature = "dap")]
+fn serve_dap(options: ParseOptions) -> Result<(), CliError> {
+    Ok(dap::stdio(options)?)
+}
+
+#[cfg(not(feature = "dap"))]
+fn serve_dap(_: ParseOptions) -> Result<(), CliError> {
+    Err(CliError::Usage("dap needs a build with the dap feature".to_string()))
+}
+
+#[cfg(feature = "lsp")]
+fn serve_lsp(options: ParseOptions) -> Result<(), CliError> {
+    Ok(lsp::stdio(options)?)
+}
+
+#[cfg(not(feature = "lsp"))]
+fn serve_lsp(_: ParseOptions) -> Result<(), CliError> {
+    Err(CliError::Usage("lsp needs a build with the lsp feature".to_string()))
+}
+
+fn debug(
+    input: &Path,
+    ir: bool,
+    journal_len: usize,
+    resume: Option<&Path>,
+    language: &Language,
+    program_io: &ProgramIo,
+) -> Result<(), CliError> {
+    let options = language.options()?;
+    // stdin is needed for commands, so '-' is not special here
+    let contents = fs::read_to_string(input).map_err(|e| CliError::io("read", input, e))?;
+    let program = expand(input, contents, language)?;
+    parse_with_options(&program.text, true, &options).map_err(|e| bracket_error(&program, e))?;
+    // the source was parsed above, so failing now is a bug
+    let mut debugger = Debugger::new(&program.text, ir, &options).map_err(|e| CliError::Internal(e.to_string()))?;
+    debugger.set_journal_len(journal_len);
+    if let Some(path) = resume {
+        debugger.restore(read_snapshot(path)?)?;
+    }
+    if let Some(reader) = program_io.reader()? {
+        debugger.set_input(reader);
+    }
+    if let Some(writer) = program_io.writer()? {
+        debugger.set_output(writer);
+    }
+    Ok(debugger.repl(&mut io::stdin().lock(), &mut io::stdout())?)
+}
+
+fn verify(
+    input: &Path,
+    cc: &str,
+    max_steps: Option<u64>,
+    input_file: Option<&Path>,
+    input_string: Option<String>,
+    language: &Language,
+) -> Result<(), CliError> {
+    let options = language.options()?;
+    let program = read_program(input, language)?;
+    parse_with_options(&program.text, true, &options).map_err(|e| bracket_error(&program, e))?;
+    let program_input = match (input_file, input_string) {
+        (Some(path), _) => fs::read(path).map_err(|e| CliError::io("read --input-file", path, e))?,
+        (None, Some(text)) => text.into_bytes(),
+        (None, None) => Vec::new(),
+    };
+    let work_dir = std::env::temp_dir().join(format!("bf-verify-{}", std::process::id()));
+    fs::create_dir_all(&work_dir).map_err(|e| CliError::io("create build directory", &work_dir, e))?;
+    let limits = Limits { max_steps, timeout: None };
+    let result = verify_each(&program.text, &program_input, cc, &work_dir, &limits, &options);
+    let _ = fs::remove_dir_all(&work_dir);
+    result
+}
+
+/// Checks the C of every optimization level and cell width against the
+/// interpreter, stopping at the first disagreement.
+fn verify_each(source: &str, input: &[u8], cc: &str, work_dir: &Path, limits: &Limits, options: &ParseOptions) -> Result<(), CliError> {
+    let mut out = io::stdout().lock();
+    for (cell_width, bits) in [(CellWidth::U8, 8), (CellWidth::U16, 16), (CellWidth::U32, 32)] {
+        for opt_level in 0..2 {
+            let transpiler = Transpiler::builder().options(options.clone()).cell_width(cell_width).opt_level(opt_level).build()?;
+            let configuration = format!("-O {}, {}-bit cells", opt_level, bits);
+            let Some(comparison) = verify_backend(source, input, cc, work_dir, limits, &transpiler)? else {
+                writeln!(out, "{}: skipped, the output depends on 8-bit cells wrapping around", configuration).map_err(CliError::stdout)?;
+                continue;
+            };
+            let Some(index) = comparison.first_difference() else {
+                writeln!(out, "{}: outputs match ({} bytes)", configuration, comparison.interpreter.len()).map_err(CliError::stdout)?;
+                continue;
+            };
+            let byte = |output: &[u8]| output.get(index).map_or("end of output".to_string(), |b| b.to_string());
+            writeln!(
+                out,
+                "{}: outputs differ at byte {}: interpreter wrote {} bytes, compiled program {} bytes",
+                configuration,
+                index,
+                comparison.interpreter.len(),
+                comparison.compiled.len()
+            )
+            .and_then(|_| writeln!(out, "  interpreter: {}", byte(&comparison.interpreter)))
+            .and_then(|_| writeln!(out, "  compiled:    {}", byte(&comparison.compiled)))
+            .map_err(CliError::stdout)?;
+            return Err(CliError::Check(format!("the generated C disagrees with the interpreter at byte {} with {}", index, configuration)));
+        }
+    }
+    Ok(())
+}
+
+fn inspect_trace(file: &Path, source: Option<&Path>, diff: Option<&Path>) -> Result<(), CliError> {
+    let events = load_trace(file)?;
+    let mut out = io::stdout().lock();
+    let Some(other) = diff else {
+        let source = match source {
+            Some(path) => Some(fs::read_to_string(path).map_err(|e| CliError::io("read --source", path, e))?),
+            None => None,
+        };
+        writeln!(out, "{:>8}  {:>6}  event", "offset", "ptr").map_err(CliError::stdout)?;
+        for event in &events {
+            writeln!(out, "{}", event.describe(source.as_deref())).map_err(CliError::stdout)?;
+        }
+        return Ok(());
+    };
+    match compare_io(&events, &load_trace(other)?) {
+        Divergence::None { events } => {
+            writeln!(out, "traces agree on all {} I/O events", events).map_err(CliError::stdout)
+        }
+        Divergence::At { index, left, right } => {
+            let describe = |event: Option<Event>| match event {
+                Some(event) => event.describe(None),
+                None => "    (end of trace)".to_string(),
+            };
+            writeln!(out, "traces diverge at I/O event {}:", index)
+                .and_then(|_| writeln!(out, "{}: {}", file.display(), describe(left)))
+                .and_then(|_| writeln!(out, "{}: {}", other.display(), describe(right)))
+                .map_err(CliError::stdout)
+        }
+    }
+}
+
+/// Prints the diagnostics of every input to stderr. Fails if any of them
+/// has errors, warnings alone pass.
+fn check_sources(inputs: &[PathBuf], language: &Language) -> Result<(), CliError> {
+    let options = language.options()?;
+    // checked in parallel, reported in order
+    let reports: Vec<Result<Vec<(Severity, String)>, CliError>> = inputs
+        .par_iter()
+        .map(|input| {
+            let program = read_program(input, language)?;
+            let rendered = check(&program.text, &options)
+                .into_iter()
+                .map(|diagnostic| match diagnostic.offset() {
+                    Some(offset) => {
+                        let (path, source, offset) = program.locate(offset);
+                        let local = diagnostic.relocate(source, offset);
+                        (local.severity, local.render(source, &path.display().to_string()))
+                    }
+                    None => (diagnostic.severity, diagnostic.render(&program.text, &input.display().to_string())),
+                })
+                .collect();
+            Ok(rendered)
+        })
+        .collect();
+    let mut errors = 0;
+    for report in reports {
+        for (severity, rendered) in report? {
+            eprintln!("{}", rendered);
+            if severity == Severity::Error {
+                errors += 1;
+            }
+        }
+    }
+    match errors {
+        0 => Ok(()),
+        1 => Err(CliError::Parse("1 error found".to_string())),
+        n => Err(CliError::Check(format!("{} errors found", n))),
+    }
+}
+
+/// Transpiles `inputs` into one program written to `output`, and writes
+/// its analysis to `report` if given.
+fn transpile(
+    inputs: &[PathBuf],
+    output: Option<&Path>,
+    report: Option<&Path>,
+    max_output_bytes: Option<usize>,
+    emit: Emit,
+    language: &Language,
+    transpiler: &Transpiler,
+) -> Result<(), CliError> {
+    let code = match (emit, report) {
+        (Emit::C, None) => transpile_joined(inputs, language, transpiler)?,
+        _ => {
+            let program = read_joined(inputs, language)?;
+            let code = generate(&program, emit, language, transpiler)?;
+            if let Some(path) = report {
+                write_report(path, &program, &language.options()?, None)?;
+            }
+            code
+        }
+    };
+    check_output_size(&code, max_output_bytes, "the generated program")?;
+    write_output(output.unwrap_or(Path::new("-")), &code)
+}
+
+/// Transpiles each of `inputs` separately into `out_dir`.
+fn transpile_each(
+    inputs: &[PathBuf],
+    out_dir: &Path,
+    max_output_bytes: Option<usize>,
+    emit: Emit,
+    language: &Language,
+    transpiler: &Transpiler,
+) -> Result<(), CliError> {
+    if let Some(input) = inputs.iter().find(|input| is_std_stream(input) || input.file_stem().is_none()) {
+        return Err(CliError::Usage(format!("--out-dir needs named input files, got '{}'", input.display())));
+    }
+    fs::create_dir_all(out_dir).map_err(|e| CliError::io("create --out-dir", out_dir, e))?;
+    // a failing input does not stop the others, all failures are reported at the end
+    let failures: Vec<CliError> = inputs
+        .par_iter()
+        .filter_map(|input| {
+            let code = match emit {
+                Emit::C => transpile_joined(std::slice::from_ref(input), language, transpiler),
+                Emit::Dot => read_program(input, language).and_then(|program| generate(&program, emit, language, transpiler)),
+            };
+            code.and_then(|code| {
+                check_output_size(&code, max_output_bytes, &format!("the output for '{}'", input.display()))?;
+                let stem = input.file_stem().expect("checked above");
+                write_output(&out_dir.join(stem).with_extension(emit.extension()), &code)
+            })
+            .err()
+        })
+        .collect();
+    match failures.len() {
+        0 => Ok(()),
+        1 => Err(failures.into_iter().next().expect("one failure")),
+        n => {
+            for failure in &failures {
+                eprintln!("error: {}", failure);
+            }
+            Err(CliError::several(&failures, format!("{} of {} inputs failed", n, inputs.len())))
+        }
+    }
+}
+
+/// What `emit` asks for, for `program`.
+fn generate(program: &Expanded, emit: Emit, language: &Language, transpiler: &Transpiler) -> Result<String, CliError> {
+    #[cfg(feature = "mmap")]
+    if language.mmap {
+        return Err(CliError::Usage("--mmap only applies to transpiling one plain Brainfuck file to C, without --report".to_string()));
+    }
+    match emit {
+        Emit::C => transpiler.transpile(&program.text).map_err(|e| transpile_error(program, e)),
+        Emit::Dot => {
+            let tokens = parse_spanned(&program.text, &language.options()?).map_err(|e| bracket_error(program, e))?;
+            let (prog, mut spans) = optimize_with_spans(&tokens);
+            // lines and columns in the file each loop comes from
+            for span in &mut spans {
+                let (_, source, offset) = program.locate(span.start);
+                (span.line, span.column) = line_column(source, offset);
+            }
+            Ok(to_dot(&prog, Some(&spans)))
+        }
+    }
+}
+
+/// `inputs` concatenated into one program.
+fn read_joined(inputs: &[PathBuf], language: &Language) -> Result<Expanded, CliError> {
+    let mut programs = Vec::new();
+    for input in inputs {
+        programs.push(read_program(input, language)?);
+    }
+    Ok(Expanded::concat(programs, "\n"))
+}
+
+/// C code for `inputs` concatenated into one program. A single file is
+/// tokenized as it is read, see `transpile_streamed`.
+fn transpile_joined(inputs: &[PathBuf], language: &Language, transpiler: &Transpiler) -> Result<String, CliError> {
+    if let [input] = inputs {
+        if let Some(code) = transpile_streamed(input, language, transpiler)? {
+            return Ok(code);
+        }
+    }
+    generate(&read_joined(inputs, language)?, Emit::C, language, transpiler)
+}
+
+/// C code for the file at `input`, tokenized in chunks as it is read, or
+/// from memory with --mmap, instead of being read into memory as text. `None`
+/// for sources that have to be read as text: stdin, includes, other dialects
+/// and literate sources.
+fn transpile_streamed(input: &Path, language: &Language, transpiler: &Transpiler) -> Result<Option<String>, CliError> {
+    let options = transpiler.options();
+    if is_std_stream(input) || language.includes || options.dialect != Dialect::Brainfuck || options.literate {
+        return Ok(None);
+    }
+    let start = Instant::now();
+    let read = || File::open(input).map_err(|e| Bf2cError::io("open source", e)).and_then(|file| transpiler.transpile_reader(file));
+    #[cfg(feature = "mmap")]
+    let code = if language.mmap { transpiler.transpile_mapped(input) } else { read() };
+    #[cfg(not(feature = "mmap"))]
+    let code = read();
+    match code {
+        Ok(code) => {
+            info!(path = %input.display(), bytes = code.len(), elapsed = ?start.elapsed(), "transpiled streamed source");
+            Ok(Some(code))
+        }
+        // read as text after all, to show the bracket in context
+        Err(Bf2cError::Parse(e)) => Err(bracket_error(&read_program(input, language)?, e)),
+        Err(Bf2cError::Io { source, .. }) => Err(CliError::io("read", input, source)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Transpiles `inputs` into one program and compiles it with `cc` into
+/// `output`, or next to the first input. Compiler warnings go to stderr.
+fn build_executable(
+    inputs: &[PathBuf],
+    output: Option<&Path>,
+    cc: &str,
+    cflags: &[String],
+    language: &Language,
+    transpiler: &Transpiler,
+) -> Result<(), CliError> {
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None if is_std_stream(&inputs[0]) => PathBuf::from("a.out"),
+        None => inputs[0].with_extension(""),
+    };
+    let code = transpile_joined(inputs, language, transpiler)?;
+    let work_dir = std::env::temp_dir().join(format!("bf-build-{}", std::process::id()));
+    fs::create_dir_all(&work_dir).map_err(|e| CliError::io("create build directory", &work_dir, e))?;
+    let start = Instant::now();
+    let result = build(&code, cc, cflags, &work_dir, &output);
+    let _ = fs::remove_dir_all(&work_dir);
+    let built = result?;
+    info!(cc, executable = %built.executable.display(), elapsed = ?start.elapsed(), "compiled");
+    eprint!("{}", built.diagnostics);
+    Ok(())
+}
+
+/// Writes the analysis of `program` to `path`, with profiling data if the
+/// program was run.
+fn write_report(path: &Path, program: &Expanded, options: &ParseOptions, profile: Option<&Profile>) -> Result<(), CliError> {
+    let html = report(&program.text, options, profile).map_err(|e| bracket_error(program, e))?;
+    fs::write(path, html).map_err(|e| CliError::io("write report", path, e))
+}
+
+/// Builds `inputs` with `cc` and runs the result on this process's stdin and
+/// stdout. Returns the program's exit code.
+fn compile_and_run(
+    inputs: &[PathBuf],
+    cc: &str,
+    max_output_bytes: Option<usize>,
+    language: &Language,
+    transpiler: &Transpiler,
+) -> Result<i32, CliError> {
+    let code = transpile_joined(inputs, language, transpiler)?;
+    check_output_size(&code, max_output_bytes, "the generated program")?;
+    let work_dir = std::env::temp_dir().join(format!("bf-run-{}", std::process::id()));
+    fs::create_dir_all(&work_dir).map_err(|e| CliError::io("create build directory", &work_dir, e))?;
+    let start = Instant::now();
+    let status = compile(&code, cc, &work_dir).and_then(|executable| {
+        info!(cc, elapsed = ?start.elapsed(), "compiled");
+        debug!(executable = %executable.display(), "running");
+        process::Command::new(&executable)
+            .status()
+            .map_err(|e| Bf2cError::Compile(format!("cannot run compiled program: {}", e)))
+    });
+    let _ = fs::remove_dir_all(&work_dir);
+    let status = status?;
+    Ok(exit_code_of(status))
+}
+
+/// Fails if `code`, described by `what`, is longer than `max` bytes.
+fn check_output_size(code: &str, max: Option<usize>, what: &str) -> Result<(), CliError> {
+    match max {
+        Some(max) if code.len() > max => Err(CliError::Limit(format!(
+            "{} is {} bytes, more than --max-output-bytes {}",
+            what,
+            code.len(),
+            max
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Exit code to forward for a finished child, using the shell's 128 + signal
+/// convention for programs killed by a signal.
+#[cfg(unix)]
+fn exit_code_of(status: process::ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status.code().or_else(|| status.signal().map(|signal| 128 + signal)).unwrap_or(error::EXIT_IO)
+}
+
+#[cfg(not(unix))]
+fn exit_code_of(status: process::ExitStatus) -> i32 {
+    status.code().unwrap_or(error::EXIT_IO)
+}
+
+fn parse_error(path: &Path, message: impl std::fmt::Display) -> CliError {
+    CliError::Parse(format!("{}: {}", path.display(), message))
+}
+
+/// Error from transpiling `program`, with brackets located by `bracket_error`.
+fn transpile_error(program: &Expanded, e: Bf2cError) -> CliError {
+    match e {
+        Bf2cError::Parse(e) => bracket_error(program, e),
+        e => e.into(),
+    }
+}
+
+/// Unmatched bracket in `program`, shown in context in the file it is in.
+fn bracket_error(program: &Expanded, e: ParseError) -> CliError {
+    let (path, source, offset) = program.locate(e.offset());
+    let (line, column) = line_column(source, offset);
+    let e = e.relocate(source, offset);
+    CliError::Parse(format!("{}:{}:{}: {}\n{}", path.display(), line, column, e, snippet(source, offset)))
+}
+
+/// Reads `path`, or stdin for `-`, as a program.
+fn read_program(path: &Path, language: &Language) -> Result<Expanded, CliError> {
+    expand(path, read_source(path)?, language)
+}
+
+/// `source`, read from `path`, with its includes expanded if enabled.
+fn expand(path: &Path, source: String, language: &Language) -> Result<Expanded, CliError> {
+    if !language.includes {
+        return Ok(Expanded::single(path, source));
+    }
+    Expanded::new(path, source).map_err(|e| match e {
+        IncludeError::Read(message) => CliError::Io(message),
+        IncludeError::Invalid(message) => CliError::Parse(message),
+    })
+}
+
+/// Reads a Brainfuck source file, or stdin for `-`.
+fn read_source(path: &Path) -> Result<String, CliError> {
+    let start = Instant::now();
+    let contents = if is_std_stream(path) {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents).map_err(|e| CliError::Io(format!("cannot read stdin: {}", e)))?;
+        contents
+    } else {
+        fs::read_to_string(path).map_err(|e| CliError::io("read", path, e))?
+    };
+    info!(path = %path.display(), bytes = contents.len(), elapsed = ?start.elapsed(), "read source");
+    Ok(contents)
+}
+
+/// Writes `code` to `path`, or to stdout for `-`.
+fn write_output(path: &Path, code: &str) -> Result<(), CliError> {
+    info!(path = %path.display(), bytes = code.len(), "writing output");
+    if is_std_stream(path) {
+        return io::stdout().write_all(code.as_bytes()).map_err(CliError::stdout);
+    }
+    fs::write(path, code).map_err(|e| CliError::io("write", path, e))
+}
+
+/// Whether `path` is `-`, which stands for stdin or stdout.
+fn is_std_stream(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+fn load_trace(path: &Path) -> Result<Vec<Event>, CliError> {
+    let file = File::open(path).map_err(|e| CliError::io("read trace", path, e))?;
+    read_trace(BufReader::new(file)).map_err(|e| parse_error(path, e))
+}
+
+fn read_snapshot(path: &Path) -> Result<Snapshot, CliError> {
+    let mut file = File::open(path).map_err(|e| CliError::io("read snapshot", path, e))?;
+    Snapshot::read(&mut file).map_err(|e| parse_error(path, e))
 }